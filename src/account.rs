@@ -0,0 +1,206 @@
+use crate::money::Money;
+use crate::{detect_delimiter, local_date_parse, local_time_parse, Strictness};
+use anyhow::anyhow;
+use chrono::{NaiveDate, NaiveTime};
+use futures::Stream;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::vec;
+
+/// What a row of the account statement is, decided by its description
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccountEventKind {
+    Deposit,
+    Withdrawal,
+    Dividend,
+    /// the tax withheld from a dividend
+    DividendTax,
+    /// transaction costs and other fees
+    Fee,
+    /// paid or received on the cash balance
+    Interest,
+    /// the cash of a buy or sell, which `CsvStream` accounts for
+    Trade,
+    /// currency exchanges and anything not recognized
+    Other,
+}
+
+impl AccountEventKind {
+    // the English and Dutch descriptions of DeGiro
+    fn from_description(description: &str) -> Self {
+        let description = description.to_lowercase();
+        let has = |markers: &[&str]| markers.iter().any(|m| description.contains(m));
+        // trades name the product, which can be anything
+        if ["buy ", "sell ", "koop ", "verkoop "]
+            .iter()
+            .any(|m| description.starts_with(m))
+        {
+            Self::Trade
+        } else if has(&["currency exchange", "valuta"]) {
+            Self::Other
+        } else if has(&["dividend tax", "dividendbelasting"]) {
+            Self::DividendTax
+        } else if has(&["dividend"]) {
+            Self::Dividend
+        } else if has(&["fee", "kosten"]) {
+            Self::Fee
+        } else if has(&["interest", "rente"]) {
+            Self::Interest
+        } else if has(&["deposit", "storting"]) && !has(&["terugstorting"]) {
+            Self::Deposit
+        } else if has(&["withdrawal", "terugstorting", "opname"]) {
+            Self::Withdrawal
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// A cash movement of the account statement
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountEvent {
+    pub date: NaiveDate,
+    pub time: Option<NaiveTime>,
+    pub product: String,
+    pub isin: String,
+    pub description: String,
+    pub kind: AccountEventKind,
+    /// positive for cash received, negative for cash paid
+    pub change: Money,
+    /// the cash balance after the movement
+    pub balance: Option<Money>,
+    pub order_id: String,
+}
+
+/// Parses the Account.csv statement of DeGiro, the cash movements of the account,
+/// into `AccountEvent`s from oldest to newest.
+/// Rows without a change, like cash sweeps, are left out
+pub struct AccountCsvStream {
+    events: vec::IntoIter<anyhow::Result<AccountEvent>>,
+    strictness: Strictness,
+}
+
+impl AccountCsvStream {
+    /// detects the delimiter from the header line of the file
+    pub fn new(mut file: File) -> anyhow::Result<Self> {
+        let mut header = String::new();
+        BufReader::new(&file).read_line(&mut header)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .delimiter(detect_delimiter(&header))
+            .flexible(true)
+            .from_reader(file);
+        let mut events: Vec<_> = rdr
+            .records()
+            .filter_map(|record| match record {
+                Ok(record) => parse_record(&record).transpose(),
+                Err(e) => Some(Err(e.into())),
+            })
+            .collect();
+
+        // the statement is newest first
+        let dates: Vec<NaiveDate> = events
+            .iter()
+            .filter_map(|event| event.as_ref().ok().map(|event| event.date))
+            .collect();
+        if let (Some(first), Some(last)) = (dates.first(), dates.last()) {
+            if first > last {
+                events.reverse();
+            }
+        }
+
+        Ok(Self {
+            events: events.into_iter(),
+            strictness: Strictness::default(),
+        })
+    }
+
+    /// opens the file at `path` and detects the delimiter
+    pub fn from_path<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).map_err(|e| anyhow!("cannot open {}: {}", path.display(), e))?;
+        Self::new(file)
+    }
+
+    /// sets how invalid rows are handled
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+}
+
+// the columns of the statement, the change and the balance
+// are a currency column followed by an amount column
+const DATE: usize = 0;
+const TIME: usize = 1;
+const PRODUCT: usize = 3;
+const ISIN: usize = 4;
+const DESCRIPTION: usize = 5;
+const CHANGE: usize = 7;
+const BALANCE: usize = 9;
+const ORDER_ID: usize = 11;
+
+// the event of a row, None when it has no change
+fn parse_record(record: &csv::StringRecord) -> anyhow::Result<Option<AccountEvent>> {
+    let field = |i: usize| record.get(i).unwrap_or_default().trim();
+    let change = match money(field(CHANGE), field(CHANGE + 1))? {
+        Some(change) => change,
+        None => return Ok(None),
+    };
+    let date = local_date_parse(field(DATE))
+        .map_err(|e| anyhow!("date: {} is not DD-MM-YYYY: {}", field(DATE), e))?;
+    let time = match field(TIME) {
+        "" => None,
+        time => Some(local_time_parse(time).map_err(|e| anyhow!("time: {}: {}", time, e))?),
+    };
+
+    Ok(Some(AccountEvent {
+        date,
+        time,
+        product: field(PRODUCT).to_string(),
+        isin: field(ISIN).to_string(),
+        description: field(DESCRIPTION).to_string(),
+        kind: AccountEventKind::from_description(field(DESCRIPTION)),
+        change,
+        balance: money(field(BALANCE), field(BALANCE + 1))?,
+        order_id: field(ORDER_ID).to_string(),
+    }))
+}
+
+// parses the amount with its currency, None when the amount is blank
+fn money(currency: &str, amount: &str) -> anyhow::Result<Option<Money>> {
+    if amount.is_empty() {
+        return Ok(None);
+    }
+    let money: Money = format!("{} {}", currency, amount)
+        .trim()
+        .parse()
+        .map_err(|e| anyhow!("{}", e))?;
+    if money.amount().is_nan() {
+        return Err(anyhow!("invalid amount: `{}`", amount));
+    }
+    Ok(Some(money))
+}
+
+impl Stream for AccountCsvStream {
+    type Item = anyhow::Result<AccountEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.events.next() {
+                Some(Err(e)) => {
+                    if let Err(e) = self.strictness.check(e) {
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                }
+                next => return Poll::Ready(next),
+            }
+        }
+    }
+}
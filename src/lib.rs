@@ -1,50 +1,104 @@
+pub mod account;
 pub mod money;
 pub mod portfolio;
+pub mod render;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod taxform;
 
 use anyhow::anyhow;
 use chrono::{NaiveDate, NaiveTime};
-use csv::DeserializeRecordsIter;
 use dateparser::parse;
-use futures::Stream;
+use decimal::d128;
+use futures::{Stream, StreamExt};
+use log::warn;
 use money::Money;
+use portfolio::{Portfolio, Report};
 use rev_lines::RevLines;
-use serde::{de, Deserialize, Serialize};
+use serde::{de, ser, Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::Write;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
 use std::iter::Peekable;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+/// How invalid input is handled,
+/// such as unparsable rows, transactions out of order,
+/// sells without a position or selling more than a position holds.
+///
+/// In lenient mode the rest of an oversell opens a short
+/// and is reported as a `Warning::UnmatchedSell`,
+/// `Portfolio::disallow_shorts` makes it an error in both modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strictness {
+    /// fail with an error
+    #[default]
+    Strict,
+    /// log a warning and skip the offending transaction
+    Lenient,
+}
+
+impl Strictness {
+    // returns the error in strict mode and logs it otherwise
+    pub(crate) fn check(&self, err: anyhow::Error) -> anyhow::Result<()> {
+        match self {
+            Self::Strict => Err(err),
+            Self::Lenient => {
+                warn!("skipping: {}", err);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TransactionType {
     Buy,
     Sell,
+    /// neither a buy nor a sell, it is not accounted for
+    Other,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "PascalCase")]
 pub struct Transaction {
-    #[serde(deserialize_with = "deserialize_naive_date")]
+    #[serde(
+        serialize_with = "serialize_naive_date",
+        deserialize_with = "deserialize_naive_date"
+    )]
     date: NaiveDate,
-    #[serde(deserialize_with = "deserialize_naive_time")]
-    time: NaiveTime,
+    #[serde(
+        serialize_with = "serialize_naive_time",
+        deserialize_with = "deserialize_naive_time"
+    )]
+    // older exports can leave the time out
+    time: Option<NaiveTime>,
     product: String,
-    #[serde(rename(deserialize = "ISIN"))]
+    #[serde(rename = "ISIN")]
     isin: String,
     reference: String,
     quantity: isize,
     venue: String,
     price: Money,
-    #[serde(rename(deserialize = "Local value"))]
+    #[serde(rename = "Local value")]
     local_value: Money,
     value: Money,
-    #[serde(rename(deserialize = "Transaction and/or third"))]
-    transaction: Option<String>,
-    #[serde(rename(deserialize = "Exchange rate"))]
+    /// transaction costs and third party fees
+    #[serde(rename = "Transaction and/or third")]
+    fee: Option<Money>,
+    #[serde(rename = "Exchange rate")]
     exchange_rate: Option<String>,
-    total: String,
-    #[serde(rename(deserialize = "Order ID"))]
+    /// the cash total after fees, None when it is blank
+    #[serde(default, deserialize_with = "deserialize_total")]
+    total: Option<Money>,
+    #[serde(rename = "Order ID")]
     order_id: String,
+    // the fee or the total of the row was a cancellation marker, see `is_cancelled`
+    #[serde(default)]
+    cancelled: bool,
 }
 
 #[derive(Debug)]
@@ -71,7 +125,7 @@ impl Transaction {
 
         Ok(Self {
             date,
-            time: NaiveTime::from_hms(1, 1, 1),
+            time: NaiveTime::from_hms_opt(1, 1, 1),
             product: "".to_string(),
             isin,
             reference: "".to_string(),
@@ -80,10 +134,11 @@ impl Transaction {
             price: Default::default(),
             value,
             local_value: Default::default(),
-            transaction: None,
+            fee: None,
             exchange_rate: None,
-            total: "".to_string(),
+            total: None,
             order_id,
+            cancelled: false,
         })
     }
 
@@ -100,27 +155,217 @@ impl Transaction {
         }
     }
 
+    /// parses a single comma delimited data line of a DeGiro export,
+    /// without the header line
+    pub fn from_csv_line(line: &str) -> anyhow::Result<Self> {
+        parse_line(HEADERS, b',', false, line)?
+            .ok_or_else(|| anyhow!("no record in line: {}", line))
+    }
+
+    /// the exchange rate from the local currency to the value,
+    /// None when it is blank or not a number
+    pub fn exchange_rate(&self) -> Option<d128> {
+        let rate = money::parse_decimal(self.exchange_rate.as_ref()?).ok()?;
+        Some(rate).filter(|r| r.is_finite() && !r.is_zero())
+    }
+
+    pub fn with_exchange_rate(mut self, rate: d128) -> Self {
+        self.exchange_rate = Some(rate.to_string());
+        self
+    }
+
+    pub fn with_fee(mut self, fee: Money) -> Self {
+        self.fee = Some(fee);
+        self
+    }
+
     pub fn date(&self) -> &NaiveDate {
         &self.date
     }
 
+    pub fn time(&self) -> Option<NaiveTime> {
+        self.time
+    }
+
+    pub fn product(&self) -> &str {
+        &self.product
+    }
+
+    pub fn isin(&self) -> &str {
+        &self.isin
+    }
+
+    pub fn reference(&self) -> &str {
+        &self.reference
+    }
+
+    pub fn order_id(&self) -> &str {
+        &self.order_id
+    }
+
+    pub fn quantity(&self) -> isize {
+        self.quantity
+    }
+
+    pub fn value(&self) -> &Money {
+        &self.value
+    }
+
+    pub fn fee(&self) -> Option<&Money> {
+        self.fee.as_ref()
+    }
+
+    /// the cash total after fees DeGiro computed, None when it is blank
+    pub fn total(&self) -> Option<&Money> {
+        self.total.as_ref()
+    }
+
+    /// converts the value, price and fee to `currency` using the exchange rate,
+    /// values without a currency are assumed to already be in it
+    pub fn to_currency(&self, currency: &str) -> anyhow::Result<Self> {
+        let currency = currency.to_lowercase();
+        let mut tr = self.clone();
+        tr.value = self.money_to_currency(&self.value, &currency)?;
+        tr.price = self.money_to_currency(&self.price, &currency)?;
+        tr.fee = match &self.fee {
+            Some(fee) => Some(self.money_to_currency(fee, &currency)?),
+            None => None,
+        };
+        Ok(tr)
+    }
+
+    fn money_to_currency(&self, money: &Money, currency: &str) -> anyhow::Result<Money> {
+        match &money.currency {
+            Some(c) if c != currency => {
+                let rate = self.exchange_rate.as_ref().ok_or_else(|| {
+                    anyhow!(
+                        "missing exchange rate from {} to {} order: {}",
+                        c,
+                        currency,
+                        self.order_id
+                    )
+                })?;
+                let rate = money::parse_decimal(rate)
+                    .ok()
+                    .filter(|r| r.is_finite() && !r.is_zero())
+                    .ok_or_else(|| anyhow!("invalid exchange rate: `{}`", rate))?;
+                Ok(money.convert(currency, rate))
+            }
+            _ => Ok(Money::with_currency(money.amount, currency.to_string())),
+        }
+    }
+
+    /// like `type`, but fails when the sign of the quantity
+    /// contradicts the sign of the value
+    pub fn checked_type(&self) -> Result<TransactionType, TransactionError> {
+        let order_id = self.order_id.clone();
+        if self.quantity.is_negative() && self.value.amount.is_negative() {
+            return Err(TransactionError::SellWithNegPrice { order_id });
+        }
+        if self.quantity.is_positive() && self.value.amount.is_positive() {
+            return Err(TransactionError::BuyingWithNegPrice { order_id });
+        }
+        Ok(self.r#type())
+    }
+
+    /// tags the price, values and fee that have no currency with `currency`
+    pub fn assume_currency(mut self, currency: &str) -> Self {
+        self.price.assume_currency(currency);
+        self.local_value.assume_currency(currency);
+        self.value.assume_currency(currency);
+        if let Some(fee) = &mut self.fee {
+            fee.assume_currency(currency);
+        }
+        self
+    }
+
+    /// tags the price, values and fee with `currency`,
+    /// replacing the currency they were parsed with
+    pub fn override_currency(mut self, currency: &str) -> Self {
+        let currency = currency.to_lowercase();
+        for money in [&mut self.price, &mut self.local_value, &mut self.value] {
+            money.currency = Some(currency.clone());
+        }
+        if let Some(fee) = &mut self.fee {
+            fee.currency = Some(currency);
+        }
+        self
+    }
+
+    /// gives the quantity the sign of the direction of the trade,
+    /// positive for a buy with a negative value and negative for a sell.
+    /// Zero values keep the quantity as is
+    pub fn sign_quantity(mut self) -> Self {
+        if self.value.amount.is_negative() {
+            self.quantity = self.quantity.abs();
+        } else if self.value.amount.is_positive() {
+            self.quantity = -self.quantity.abs();
+        }
+        self
+    }
+
     pub fn r#type(&self) -> TransactionType {
         match self.value.amount.is_negative() {
             true => TransactionType::Buy,
             false => TransactionType::Sell,
         }
     }
+
+    /// whether the row is one of the separate transaction cost rows
+    /// DeGiro adds under the order id of the trade
+    pub fn is_cost_row(&self) -> bool {
+        let product = self.product.to_lowercase();
+        self.quantity == 0
+            && product.contains("degiro")
+            && (product.contains("cost") || product.contains("fee"))
+    }
+
+    /// whether the row is the accrued interest paid or received
+    /// with a bond trade, rather than the trade itself
+    pub fn is_accrued_interest(&self) -> bool {
+        let product = self.product.to_lowercase();
+        product.contains("accrued interest") || product.contains("opgelopen rente")
+    }
+
+    /// whether the row is a dividend paid in shares (DRIP),
+    /// which comes in as a buy of less than a cent
+    pub fn is_drip(&self) -> bool {
+        self.quantity > 0 && self.value.amount.abs() < d128!(0.01)
+    }
+
+    /// whether the row is marked as a cancelled or pending order
+    /// in its fee or total, it did not change the position
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
+fn serialize_naive_date<S>(date: &NaiveDate, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: ser::Serializer,
+{
+    serializer.collect_str(&date.format(DATE_FORMAT))
+}
+
+fn serialize_naive_time<S>(time: &Option<NaiveTime>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: ser::Serializer,
+{
+    match time {
+        Some(time) => serializer.collect_str(&time.format(TIME_FORMAT_SECONDS)),
+        None => serializer.serialize_str(""),
+    }
 }
 
 fn deserialize_naive_date<'de, D>(deserializer: D) -> Result<NaiveDate, D::Error>
 where
     D: de::Deserializer<'de>,
 {
-    let s: &str = de::Deserialize::deserialize(deserializer)?;
-    let dt = match local_date_parse(s) {
+    let s: String = de::Deserialize::deserialize(deserializer)?;
+    let dt = match local_date_parse(&s) {
         Ok(dt) => dt,
         Err(parse_err) => {
-            let dt = parse(s)
+            let dt = parse(&s)
                 .map_err(|e| de::Error::custom(format!("error: {} error: {}", parse_err, e)))?;
             dt.naive_local().date()
         }
@@ -128,37 +373,180 @@ where
     Ok(dt)
 }
 
-fn deserialize_naive_time<'de, D>(deserializer: D) -> Result<NaiveTime, D::Error>
+fn deserialize_naive_time<'de, D>(deserializer: D) -> Result<Option<NaiveTime>, D::Error>
 where
     D: de::Deserializer<'de>,
 {
-    let s: &str = de::Deserialize::deserialize(deserializer)?;
-    let nt = match local_time_parse(s) {
+    let s: String = de::Deserialize::deserialize(deserializer)?;
+    if s.trim().is_empty() {
+        return Ok(None);
+    }
+    let nt = match local_time_parse(&s) {
         Ok(dt) => dt,
         Err(parse_err) => {
-            let dt = parse(s).map_err(|e| de::Error::custom(format!("{}\n{}", parse_err, e)))?;
+            let dt = parse(&s).map_err(|e| de::Error::custom(format!("{}\n{}", parse_err, e)))?;
             dt.naive_local().time()
         }
     };
-    Ok(nt)
+    Ok(Some(nt))
+}
+
+// a total that is not an amount fails the row, like the other amounts
+fn deserialize_total<'de, D>(deserializer: D) -> Result<Option<Money>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let s: Option<String> = de::Deserialize::deserialize(deserializer)?;
+    let s = match s {
+        Some(s) if !s.trim().is_empty() => s,
+        _ => return Ok(None),
+    };
+    s.parse::<Money>()
+        .ok()
+        .filter(|total| !total.amount.is_nan())
+        .map(Some)
+        .ok_or_else(|| de::Error::custom(format!("invalid total: `{}`", s)))
 }
 
+const DATE_FORMAT: &str = "%d-%m-%Y";
+const TIME_FORMAT: &str = "%H:%M";
+// DeGiro exports only minutes, but constructed
+// transactions may carry seconds that must survive serialization
+pub(crate) const TIME_FORMAT_SECONDS: &str = "%H:%M:%S";
+
 fn local_date_parse(s: &str) -> Result<NaiveDate, chrono::ParseError> {
-    NaiveDate::parse_from_str(s, "%d-%m-%Y")
+    NaiveDate::parse_from_str(s, DATE_FORMAT)
 }
 
 fn local_time_parse(s: &str) -> Result<NaiveTime, chrono::ParseError> {
-    NaiveTime::parse_from_str(s, "%H:%M")
+    NaiveTime::parse_from_str(s, TIME_FORMAT)
+        .or_else(|_| NaiveTime::parse_from_str(s, TIME_FORMAT_SECONDS))
 }
 
 pub struct CsvStream {
     parser: ReverseCsv,
+    strictness: Strictness,
+    unsigned_quantities: bool,
+    assumed_currency: Option<String>,
 }
 
 impl CsvStream {
+    /// detects the delimiter from the header line of the file
     pub fn new(file: File) -> std::io::Result<Self> {
-        let parser = ReverseCsv::new(file)?;
-        Ok(Self { parser })
+        let parser = ReverseCsv::new(file, None)?;
+        Ok(Self {
+            parser,
+            strictness: Strictness::default(),
+            unsigned_quantities: false,
+            assumed_currency: None,
+        })
+    }
+
+    /// parses with comma delimited `headers` instead of the DeGiro header line,
+    /// for files with the same columns in another order.
+    /// The column names are the ones of the DeGiro header line
+    pub fn with_headers(file: File, headers: &str) -> std::io::Result<Self> {
+        let parser = ReverseCsv::with_headers(file, None, headers)?;
+        Ok(Self {
+            parser,
+            strictness: Strictness::default(),
+            unsigned_quantities: false,
+            assumed_currency: None,
+        })
+    }
+
+    /// opens the file at `path` and detects the delimiter
+    pub fn from_path<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).map_err(|e| anyhow!("cannot open {}: {}", path.display(), e))?;
+        Ok(Self::new(file)?)
+    }
+
+    /// uses the given delimiter instead of detecting it
+    pub fn with_delimiter(file: File, delimiter: u8) -> std::io::Result<Self> {
+        let parser = ReverseCsv::new(file, Some(delimiter))?;
+        Ok(Self {
+            parser,
+            strictness: Strictness::default(),
+            unsigned_quantities: false,
+            assumed_currency: None,
+        })
+    }
+
+    /// the hex SHA-256 of the file, to record which file produced a report
+    #[cfg(feature = "source-hash")]
+    pub fn source_hash(&self) -> &str {
+        &self.parser.source_hash
+    }
+
+    /// converts every transaction to `currency` as it is read
+    pub fn into_base_currency(
+        self,
+        currency: &str,
+    ) -> impl Stream<Item = anyhow::Result<Transaction>> {
+        base_currency(self, currency)
+    }
+
+    /// only yields the transactions of `type` and the errors,
+    /// see `filter_type`
+    pub fn filter_type(
+        self,
+        r#type: TransactionType,
+    ) -> impl Stream<Item = anyhow::Result<Transaction>> {
+        filter_type(self, r#type)
+    }
+
+    /// sets whether unparsable rows are returned as errors or skipped
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// for exports where the quantity is always positive,
+    /// signs it by the value with `Transaction::sign_quantity`
+    pub fn unsigned_quantities(mut self, unsigned: bool) -> Self {
+        self.unsigned_quantities = unsigned;
+        self
+    }
+
+    /// only accepts dates as DD-MM-YYYY, like DeGiro exports them,
+    /// instead of guessing the format of other dates
+    pub fn strict_dates(mut self, strict: bool) -> Self {
+        self.parser.strict_dates = strict;
+        self
+    }
+
+    /// tags the amounts parsed without a currency with `currency`,
+    /// see `Transaction::assume_currency`
+    pub fn assume_currency(mut self, currency: &str) -> Self {
+        self.assumed_currency = Some(currency.to_string());
+        self
+    }
+
+    /// reads the whole file and returns its first and last transaction date,
+    /// None when it has no transactions
+    pub async fn date_range(self) -> anyhow::Result<Option<(NaiveDate, NaiveDate)>> {
+        let mut range: Option<(NaiveDate, NaiveDate)> = None;
+        let tr_stream = self;
+        futures::pin_mut!(tr_stream);
+        while let Some(tr) = tr_stream.next().await {
+            let date = tr?.date;
+            let (first, last) = range.get_or_insert((date, date));
+            *first = date.min(*first);
+            *last = date.max(*last);
+        }
+        Ok(range)
+    }
+
+    fn normalize(&self, mut tr: Transaction) -> Transaction {
+        if self.unsigned_quantities {
+            tr = tr.sign_quantity();
+        }
+        if let Some(currency) = &self.assumed_currency {
+            tr = tr.assume_currency(currency);
+        }
+        tr
     }
 }
 
@@ -166,28 +554,489 @@ impl Stream for CsvStream {
     type Item = anyhow::Result<Transaction>;
 
     fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let next = self
-            .as_mut()
-            .parser
-            .next()
-            .map(|res| res.map_err(|e| anyhow!("{}", e)));
-        Poll::Ready(next)
+        loop {
+            let next = self.as_mut().parser.next();
+
+            if let Some(Err(e)) = next {
+                if let Err(e) = self.strictness.check(e) {
+                    return Poll::Ready(Some(Err(e)));
+                }
+                continue;
+            }
+            return Poll::Ready(next.map(|tr| tr.map(|tr| self.normalize(tr))));
+        }
+    }
+}
+
+/// converts every transaction of the stream to `currency`,
+/// using `Transaction::to_currency`
+pub fn base_currency<S>(
+    tr_stream: S,
+    currency: &str,
+) -> impl Stream<Item = anyhow::Result<Transaction>>
+where
+    S: Stream<Item = anyhow::Result<Transaction>>,
+{
+    let currency = currency.to_lowercase();
+    tr_stream.map(move |tr| tr.and_then(|tr| tr.to_currency(&currency)))
+}
+
+/// drops the transactions that `Transaction::type` does not classify as `type`,
+/// errors are kept
+pub fn filter_type<S>(
+    tr_stream: S,
+    r#type: TransactionType,
+) -> impl Stream<Item = anyhow::Result<Transaction>>
+where
+    S: Stream<Item = anyhow::Result<Transaction>>,
+{
+    tr_stream.filter(move |tr| {
+        let keep = tr.as_ref().map_or(true, |tr| tr.r#type() == r#type);
+        futures::future::ready(keep)
+    })
+}
+
+/// returns the `.csv` files of `dir`, sorted by name
+pub fn csv_files<P: AsRef<Path>>(dir: P) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file()
+            && path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("csv"))
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// reports `year` for every `.csv` file of `dir`, with the portfolio
+/// `portfolio` returns for the path of the file,
+/// returns the reports merged together and the report of every file
+pub async fn report_dir<P, F, S>(
+    dir: P,
+    year: i32,
+    portfolio: F,
+) -> anyhow::Result<(Report, Vec<(PathBuf, Report)>)>
+where
+    P: AsRef<Path>,
+    F: Fn(&Path) -> anyhow::Result<Portfolio<S>>,
+    S: Stream<Item = anyhow::Result<Transaction>>,
+{
+    let dir = dir.as_ref();
+    let mut reports = Vec::new();
+    for path in csv_files(dir)? {
+        let report = portfolio(&path)
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))?
+            .report(year)
+            .await
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        reports.push((path, report));
+    }
+
+    let mut files = reports.iter();
+    let mut combined = match files.next() {
+        Some((_, report)) => report.clone(),
+        None => return Err(anyhow!("no csv files in {}", dir.display())),
+    };
+    for (_, report) in files {
+        combined.merge(report.clone())?;
+    }
+    Ok((combined, reports))
+}
+
+/// reports `year` for transactions already in memory, without a stream,
+/// blocking until the report is done
+pub fn compute_report(
+    transactions: &[Transaction],
+    year: i32,
+    years_carry_losses: u8,
+) -> anyhow::Result<Report> {
+    let tr_stream = futures::stream::iter(transactions.iter().cloned().map(Ok));
+    futures::executor::block_on(
+        Portfolio::with_carry_losses(tr_stream, years_carry_losses).report(year),
+    )
+}
+
+/// returns the currency of the values in the first `sample` transactions,
+/// an error when they are in more than one currency
+pub async fn detect_currency<S>(tr_stream: S, sample: usize) -> anyhow::Result<Option<String>>
+where
+    S: Stream<Item = anyhow::Result<Transaction>>,
+{
+    let transactions = tr_stream.take(sample).collect::<Vec<_>>().await;
+    let mut currencies = BTreeSet::new();
+    for tr in transactions {
+        if let Some(currency) = tr?.value.currency {
+            currencies.insert(currency);
+        }
+    }
+    match currencies.len() {
+        0 | 1 => Ok(currencies.into_iter().next()),
+        _ => Err(anyhow!(
+            "transactions in multiple currencies: {:?}",
+            currencies
+        )),
+    }
+}
+
+/// buffers the whole stream and yields it sorted by date, time and order id,
+/// so the input may come in any order. Errors are yielded first
+pub fn sorted<S>(tr_stream: S) -> impl Stream<Item = anyhow::Result<Transaction>>
+where
+    S: Stream<Item = anyhow::Result<Transaction>>,
+{
+    futures::stream::once(tr_stream.collect::<Vec<_>>()).flat_map(|mut transactions| {
+        transactions.sort_by(|a, b| match (a, b) {
+            (Ok(a), Ok(b)) => (a.date, a.time, &a.order_id).cmp(&(b.date, b.time, &b.order_id)),
+            (Err(_), Ok(_)) => std::cmp::Ordering::Less,
+            (Ok(_), Err(_)) => std::cmp::Ordering::Greater,
+            (Err(_), Err(_)) => std::cmp::Ordering::Equal,
+        });
+        futures::stream::iter(transactions)
+    })
+}
+
+/// drops the reversal rows DeGiro adds to correct a transaction
+/// together with the transaction they reverse: the same order id and ISIN
+/// with the opposite quantity and value. The whole stream is buffered
+pub fn cancel_reversals<S>(tr_stream: S) -> impl Stream<Item = anyhow::Result<Transaction>>
+where
+    S: Stream<Item = anyhow::Result<Transaction>>,
+{
+    futures::stream::once(tr_stream.collect::<Vec<_>>()).flat_map(|transactions| {
+        let mut kept: Vec<Option<anyhow::Result<Transaction>>> = Vec::new();
+        // indexes in `kept` of the transactions not reversed yet per order
+        let mut orders: HashMap<(String, String), Vec<usize>> = HashMap::new();
+        for tr in transactions {
+            let key = match &tr {
+                Ok(tr) => (tr.order_id.clone(), tr.isin.clone()),
+                Err(_) => {
+                    kept.push(Some(tr));
+                    continue;
+                }
+            };
+            let indexes = orders.entry(key).or_default();
+            let reversed = indexes.iter().position(|i| match (&kept[*i], &tr) {
+                (Some(Ok(earlier)), Ok(tr)) => {
+                    tr.quantity == -earlier.quantity && tr.value.amount == -earlier.value.amount
+                }
+                _ => false,
+            });
+            match reversed {
+                Some(position) => kept[indexes.remove(position)] = None,
+                None => {
+                    indexes.push(kept.len());
+                    kept.push(Some(tr));
+                }
+            }
+        }
+        futures::stream::iter(kept.into_iter().flatten())
+    })
+}
+
+/// folds the separate transaction cost rows into the value
+/// of the trade with the same order id, so they raise its basis
+/// or lower its proceeds. The fee is left alone, or the cost would count twice.
+/// The whole stream is buffered as the cost row
+/// can come before or after the trade, cost rows without a trade are kept
+pub fn fold_costs<S>(tr_stream: S) -> impl Stream<Item = anyhow::Result<Transaction>>
+where
+    S: Stream<Item = anyhow::Result<Transaction>>,
+{
+    futures::stream::once(tr_stream.collect::<Vec<_>>()).flat_map(|transactions| {
+        let trades: HashSet<String> = transactions
+            .iter()
+            .flatten()
+            .filter(|tr| !tr.is_cost_row())
+            .map(|tr| tr.order_id.clone())
+            .collect();
+        let mut costs: HashMap<String, Money> = Default::default();
+        for tr in transactions.iter().flatten() {
+            if tr.is_cost_row() && trades.contains(&tr.order_id) {
+                let cost = costs.entry(tr.order_id.clone()).or_default();
+                if let Err(e) = cost.add(&tr.value) {
+                    warn!("cost row order: {} {}", tr.order_id, e);
+                }
+            }
+        }
+
+        let folded: Vec<_> = transactions
+            .into_iter()
+            .filter(|tr| match tr {
+                Ok(tr) => !(tr.is_cost_row() && trades.contains(&tr.order_id)),
+                Err(_) => true,
+            })
+            .map(|tr| {
+                let mut tr = tr?;
+                // a trade with several fills gets the costs on its first one
+                if let Some(cost) = costs.remove(&tr.order_id) {
+                    tr.value.add(&cost)?;
+                    // the total of DeGiro leaves out the separate cost rows
+                    if let Some(total) = &mut tr.total {
+                        total.amount += cost.amount;
+                    }
+                }
+                Ok(tr)
+            })
+            .collect();
+        futures::stream::iter(folded)
+    })
+}
+
+/// merges the fills of an order into its first one, summing up quantity,
+/// value and fee and weighting the price by quantity.
+/// The whole stream is buffered as fills need not be adjacent
+pub fn aggregate_fills<S>(tr_stream: S) -> impl Stream<Item = anyhow::Result<Transaction>>
+where
+    S: Stream<Item = anyhow::Result<Transaction>>,
+{
+    futures::stream::once(tr_stream.collect::<Vec<_>>()).flat_map(|transactions| {
+        let mut merged: Vec<anyhow::Result<Transaction>> = Vec::new();
+        // index of the first fill of every order in `merged`
+        let mut orders: HashMap<(String, String), usize> = HashMap::new();
+
+        for tr in transactions {
+            let tr = match tr {
+                Ok(tr) if !tr.order_id.is_empty() => tr,
+                other => {
+                    merged.push(other);
+                    continue;
+                }
+            };
+            let key = (tr.order_id.clone(), tr.isin.clone());
+            match orders.get(&key).map(|i| &mut merged[*i]) {
+                Some(Ok(first)) => {
+                    if let Err(e) = merge_fill(first, &tr) {
+                        merged.push(Err(anyhow!("order: {} {}", tr.order_id, e)));
+                    }
+                }
+                _ => {
+                    orders.insert(key, merged.len());
+                    merged.push(Ok(tr));
+                }
+            }
+        }
+        futures::stream::iter(merged)
+    })
+}
+
+fn merge_fill(first: &mut Transaction, fill: &Transaction) -> Result<(), money::Error> {
+    let mut price = first.price.clone();
+    price.mul(first.quantity.abs())?;
+    let mut fill_price = fill.price.clone();
+    fill_price.mul(fill.quantity.abs())?;
+    price.add(&fill_price)?;
+
+    first.quantity += fill.quantity;
+    if first.quantity != 0 {
+        price.div(first.quantity.abs())?;
+    }
+    first.price = price;
+    first.value.add(&fill.value)?;
+    first.local_value.add(&fill.local_value)?;
+    if let Some(fee) = &fill.fee {
+        first.fee.get_or_insert_with(Money::default).add(fee)?;
     }
+    Ok(())
 }
 
 struct ReverseCsv {
-    rev_lines: Peekable<RevLines<File>>,
+    // the lines from the bottom up, or after the header for oldest first files
+    rev_lines: Peekable<Box<dyn Iterator<Item = String> + Send>>,
+    // the file is oldest first, so it is read from the top down
+    ascending: bool,
+    delimiter: u8,
+    headers: String,
+    strict_dates: bool,
+    // the numbers are like `1.234,56`, detected from the first lines
+    decimal_comma: bool,
+    #[cfg(feature = "source-hash")]
+    source_hash: String,
 }
 
 impl ReverseCsv {
-    pub fn new(file: File) -> std::io::Result<Self> {
+    pub fn new(file: File, delimiter: Option<u8>) -> std::io::Result<Self> {
+        Self::with_headers(file, delimiter, HEADERS)
+    }
+
+    // `headers` are comma delimited and replace the header line of the file
+    fn with_headers(mut file: File, delimiter: Option<u8>, headers: &str) -> std::io::Result<Self> {
+        #[cfg(feature = "source-hash")]
+        let source_hash = hash_file(&mut file)?;
+        let mut sample = BufReader::new(&file);
+        let mut header = String::new();
+        sample.read_line(&mut header)?;
+        let sample: Vec<String> = sample
+            .lines()
+            .map_while(Result::ok)
+            .take(DECIMAL_SAMPLE)
+            .collect();
+        file.seek(SeekFrom::Start(0))?;
+        let delimiter = delimiter.unwrap_or_else(|| detect_delimiter(&header));
+
+        let mut headers = headers
+            .trim_end()
+            .replace(',', &(delimiter as char).to_string());
+        headers.push('\n');
+        let decimal_comma = detect_decimal_comma(&headers, delimiter, &sample);
+        let ascending = is_ascending(&headers, delimiter, decimal_comma, &sample);
+
         let reader = BufReader::new(file);
-        let rev_lines = RevLines::new(reader)?.peekable();
+        let lines: Box<dyn Iterator<Item = String> + Send> = match ascending {
+            true => Box::new(reader.lines().map_while(Result::ok).skip(1)),
+            false => Box::new(RevLines::new(reader)?),
+        };
+
+        Ok(Self {
+            rev_lines: lines.peekable(),
+            ascending,
+            delimiter,
+            headers,
+            strict_dates: false,
+            decimal_comma,
+            #[cfg(feature = "source-hash")]
+            source_hash,
+        })
+    }
+}
+
+// the hex SHA-256 of the whole file, which is left at the start
+#[cfg(feature = "source-hash")]
+fn hash_file(file: &mut File) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    std::io::copy(file, &mut hasher)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// DeGiro exports use `,` but some locales use `;`
+// because the comma is their decimal separator.
+// On a tie the comma wins.
+fn detect_delimiter(header: &str) -> u8 {
+    let count = |d: u8| header.bytes().filter(|b| *b == d).count();
+
+    [b';', b'\t']
+        .iter()
+        .copied()
+        .filter(|d| count(*d) > count(b','))
+        .max_by_key(|d| count(*d))
+        .unwrap_or(b',')
+}
 
-        Ok(Self { rev_lines })
+// whether the dates of the first `lines` under the header go from old to new,
+// unlike the newest first DeGiro exports
+fn is_ascending(headers: &str, delimiter: u8, decimal_comma: bool, lines: &[String]) -> bool {
+    let dates: Vec<NaiveDate> = lines
+        .iter()
+        .filter_map(|line| {
+            parse_line(
+                headers,
+                delimiter,
+                decimal_comma,
+                line.trim_end_matches('\r'),
+            )
+            .ok()?
+        })
+        .map(|tr| tr.date)
+        .collect();
+    match (dates.first(), dates.last()) {
+        (Some(first), Some(last)) => first < last,
+        _ => false,
+    }
+}
+
+// how many lines after the header decide the decimal separator and the order
+const DECIMAL_SAMPLE: usize = 10;
+
+// the columns with numbers, which can have either decimal separator
+const DECIMAL_COLUMNS: [&str; 6] = [
+    "Price",
+    "Local value",
+    "Value",
+    "Exchange rate",
+    "Transaction and/or third",
+    "Total",
+];
+
+// whether the numbers of `lines` use a decimal comma, like `1.234,56`.
+// The numbers that tell, like `12,5` or `1,234.5`, vote
+// and on a tie the dot wins
+fn detect_decimal_comma(headers: &str, delimiter: u8, lines: &[String]) -> bool {
+    let mut input = headers.to_string();
+    for line in lines {
+        input.push_str(line.trim_end_matches('\r'));
+        input.push('\n');
+    }
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(input.as_bytes());
+    let columns: Vec<usize> = match rdr.headers() {
+        Ok(headers) => headers
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| DECIMAL_COLUMNS.contains(h))
+            .map(|(i, _)| i)
+            .collect(),
+        Err(_) => return false,
+    };
+
+    let (mut commas, mut dots) = (0, 0);
+    for record in rdr.records().filter_map(Result::ok) {
+        for cell in columns.iter().filter_map(|i| record.get(*i)) {
+            match decimal_separator(cell) {
+                Some(',') => commas += 1,
+                Some(_) => dots += 1,
+                None => {}
+            }
+        }
+    }
+    commas > dots
+}
+
+// the decimal separator of a number, None without one
+// or when it is ambiguous like `1,234`
+fn decimal_separator(cell: &str) -> Option<char> {
+    let number = cell.trim_matches(|c: char| !c.is_ascii_digit());
+    let last = number.rfind([',', '.'])?;
+    let separator = if number[last..].starts_with(',') {
+        ','
+    } else {
+        '.'
+    };
+    let other = if separator == ',' { '.' } else { ',' };
+    if number.contains(other) {
+        return Some(separator);
+    }
+    // a repeated separator groups thousands
+    if number.matches(separator).count() > 1 {
+        return Some(other);
+    }
+    match number.len() - last - 1 {
+        3 => None,
+        _ => Some(separator),
     }
 }
 
+// rewrites the numbers of a decimal comma file to the `1234.56` that `Money` parses
+fn to_decimal_dot(headers: &csv::StringRecord, record: &csv::StringRecord) -> csv::StringRecord {
+    headers
+        .iter()
+        .zip(record.iter())
+        .map(|(header, cell)| match DECIMAL_COLUMNS.contains(&header) {
+            true => cell.replace('.', "").replace(',', "."),
+            false => cell.to_string(),
+        })
+        .collect()
+}
+
 const HEADERS: &str = "Date,Time,Product,ISIN,Reference,Venue,Quantity,Price,,Local value,,Value,,Exchange rate,Transaction and/or third,,Total,,Order ID\n";
 
 impl Iterator for ReverseCsv {
@@ -196,25 +1045,236 @@ impl Iterator for ReverseCsv {
     fn next(&mut self) -> Option<Self::Item> {
         let line = self.rev_lines.next()?;
         // if it is the first/headers skip
-        self.rev_lines.peek()?;
+        if !self.ascending {
+            self.rev_lines.peek()?;
+        }
+        // files with CRLF or mixed line endings
+        let line = line.trim_end_matches('\r');
+
+        if self.strict_dates {
+            if let Err(e) = check_date(&self.headers, self.delimiter, line) {
+                return Some(Err(e));
+            }
+        }
+        parse_line(&self.headers, self.delimiter, self.decimal_comma, line).transpose()
+    }
+}
+
+// fails unless the date of `line` is in the format of DeGiro,
+// instead of leaving a guess to dateparser
+fn check_date(headers: &str, delimiter: u8, line: &str) -> anyhow::Result<()> {
+    let mut input = headers.to_string();
+    input.write_str(line).unwrap();
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(delimiter)
+        .from_reader(input.as_bytes());
+    let column = rdr.headers()?.iter().position(|h| h == "Date");
+    let (column, record) = match (column, rdr.records().next()) {
+        (Some(column), Some(record)) => (column, record?),
+        _ => return Ok(()),
+    };
+    let date = &record[column];
+    local_date_parse(date)
+        .map(|_| ())
+        .map_err(|e| anyhow!("date: {} is not DD-MM-YYYY: {}", date, e))
+}
+
+// parses `line` as the record under `headers`, an empty line has none.
+// With `decimal_comma` the numbers are like `1.234,56`
+fn parse_line(
+    headers: &str,
+    delimiter: u8,
+    decimal_comma: bool,
+    line: &str,
+) -> anyhow::Result<Option<Transaction>> {
+    let mut input = headers.to_string();
+    input.write_str(line).unwrap();
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(delimiter)
+        .from_reader(input.as_bytes());
+    let headers = rdr.headers().map_err(|e| anyhow!("{}", e))?.clone();
+    let mut iter = rdr.records();
+
+    let record = match iter.next() {
+        Some(record) => record.map_err(|e| anyhow!("{}", e))?,
+        None => return Ok(None),
+    };
+
+    if iter.next().is_some() {
+        return Err(anyhow!("expected a single record in line: {}", line));
+    }
+
+    let record = match decimal_comma {
+        true => to_decimal_dot(&headers, &record),
+        false => record,
+    };
+    let (record, cancelled) = take_cancel_markers(&headers, &record);
+    record
+        .deserialize(Some(&headers))
+        .map(|tr| Some(Transaction { cancelled, ..tr }))
+        .map_err(|e| anyhow!("{}", e))
+}
 
-        let mut input = HEADERS.to_string();
-        input.write_str(&line).unwrap();
+// the columns that mark a cancelled or pending order instead of an amount
+const MARKER_COLUMNS: [&str; 2] = ["Transaction and/or third", "Total"];
 
-        let mut rdr = csv::ReaderBuilder::new()
-            .has_headers(true)
-            .from_reader(input.as_bytes());
-        let mut iter: DeserializeRecordsIter<_, Transaction> = rdr.deserialize();
+// blanks the cancellation markers of `record` before they are parsed as amounts,
+// returning whether there was one
+fn take_cancel_markers(
+    headers: &csv::StringRecord,
+    record: &csv::StringRecord,
+) -> (csv::StringRecord, bool) {
+    let mut cancelled = false;
+    let record = headers
+        .iter()
+        .zip(record.iter())
+        .map(|(header, cell)| {
+            let lower = cell.to_lowercase();
+            let marker = MARKER_COLUMNS.contains(&header)
+                && ["cancel", "annul", "pending"]
+                    .iter()
+                    .any(|marker| lower.contains(marker));
+            cancelled |= marker;
+            match marker {
+                true => "",
+                false => cell,
+            }
+        })
+        .collect();
+    (record, cancelled)
+}
 
-        let item = iter.next()?;
+#[cfg(test)]
+mod test {
+    use crate::portfolio::Portfolio;
+    use crate::{
+        aggregate_fills, cancel_reversals, compute_report, detect_decimal_comma, to_decimal_dot,
+        Money, Transaction,
+    };
+    use chrono::NaiveDate;
+    use decimal::d128;
+    use futures::{stream, StreamExt};
 
-        assert!(iter.peekable().peek().is_none());
+    // a transaction with the order id `id`, which most tests are made of
+    pub(crate) fn trade(date: NaiveDate, isin: &str, quantity: isize, value: i32) -> Transaction {
+        Transaction::new_unchecked(
+            date,
+            isin.to_string(),
+            quantity,
+            Money::new(d128::from(value)),
+            "id".to_string(),
+        )
+    }
 
-        let res = match item {
-            Ok(k) => anyhow::Result::Ok(k),
-            Err(e) => anyhow::Result::Err(anyhow!("{}", e)),
+    #[tokio::test]
+    async fn merges_fills() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let fill = |quantity: isize, price: i32| {
+            let mut tr = Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                quantity,
+                Money::new(d128::from(-price * quantity as i32)),
+                "order".to_string(),
+            );
+            tr.price = Money::new(d128::from(price));
+            Ok(tr)
         };
 
-        Some(res)
+        let merged: Vec<_> =
+            aggregate_fills(stream::iter(vec![fill(1, 10), fill(2, 13), fill(3, 14)]))
+                .collect()
+                .await;
+
+        assert_eq!(merged.len(), 1);
+        let tr = merged[0].as_ref().unwrap();
+        assert_eq!(tr.quantity(), 6);
+        assert_eq!(*tr.value(), Money::new(d128::from(-78)));
+        assert_eq!(tr.price, Money::new(d128::from(13)));
+    }
+
+    #[test]
+    fn assume_currency() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let eur = Money::with_currency(d128::from(-10), "EUR".to_string());
+        let tr = Transaction::new_unchecked(
+            date,
+            "1".to_string(),
+            1,
+            Money::new(d128::from(-20)),
+            "order".to_string(),
+        );
+
+        let mut value = tr.value.clone();
+        assert!(value.add(&eur).is_err());
+
+        let tr = tr.assume_currency("eur");
+        assert_eq!(tr.value().currency(), Some("eur"));
+        let mut value = tr.value.clone();
+        value.add(&eur).unwrap();
+        assert_eq!(
+            value,
+            Money::with_currency(d128::from(-30), "eur".to_string())
+        );
+    }
+
+    #[test]
+    fn compute_report_sync() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let transactions = [trade(date, "1", 2, -200), trade(date, "1", -2, 250)];
+
+        let report = compute_report(&transactions, 2021, 0).unwrap();
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(50)));
+    }
+
+    #[tokio::test]
+    async fn reversals() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let reversed = |quantity, value| Transaction {
+            order_id: "reversed".to_string(),
+            ..trade(date, "1", quantity, value)
+        };
+        let transactions = || {
+            stream::iter(vec![
+                Ok(reversed(2, -200)),
+                Ok(trade(date, "1", 1, -120)),
+                Ok(reversed(-2, 200)),
+            ])
+        };
+
+        let kept: Vec<_> = cancel_reversals(transactions())
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].order_id(), "id");
+
+        let report = Portfolio::new(cancel_reversals(transactions()))
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(report.profit().unwrap(), Money::default());
+        let positions = Portfolio::new(cancel_reversals(transactions()))
+            .positions_as_of(date)
+            .await
+            .unwrap();
+        assert_eq!(positions["1"], (1, Money::new(d128::from(120))));
+    }
+
+    #[test]
+    fn decimal_comma_thousands() {
+        // `12,500` could group thousands or not, only `1.5` counts
+        let lines = ["\"12,500\",\"12,500\"".to_string(), "1.5,1".to_string()];
+        assert!(!detect_decimal_comma("Price,Value\n", b',', &lines));
+
+        // in a decimal comma file every dot groups thousands
+        let headers = csv::StringRecord::from(vec!["Price", "Value"]);
+        let record = csv::StringRecord::from(vec!["1.234", "-2.767,50"]);
+        let record = to_decimal_dot(&headers, &record);
+        assert_eq!(record, csv::StringRecord::from(vec!["1234", "-2767.50"]));
     }
 }
@@ -1,18 +1,120 @@
+use anyhow::anyhow;
+use chrono::NaiveDate;
 use clap::{ArgEnum, Parser, Subcommand};
-use degiro_tax_report::portfolio::Portfolio;
-use degiro_tax_report::CsvStream;
+use degiro_tax_report::money::{Locale, Money, Rounding};
+use degiro_tax_report::portfolio::{CostBasisMethod, Portfolio, ReportStyle};
+use degiro_tax_report::render::{
+    write_equity_curve, Csv, Html, Json, Profit, ReportRenderer, Text, Toml,
+};
+use degiro_tax_report::{
+    aggregate_fills, cancel_reversals, fold_costs, report_dir, sorted, CsvStream, Strictness,
+    Transaction,
+};
+use futures::Stream;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 
 #[derive(Parser)]
 #[clap(author, version, about)]
 struct Cli {
+    #[clap(short, long, required_unless_present = "dir")]
+    file: Option<String>,
+    /// reports every `.csv` file of the directory and all of them combined
+    #[clap(long, conflicts_with = "file")]
+    dir: Option<String>,
+    /// required here or in the config file
     #[clap(short, long)]
-    file: String,
+    year: Option<i32>,
+    /// 0 by default
     #[clap(short, long)]
-    year: i32,
-    #[clap(short, long, default_value_t = 0)]
-    carry_losses_years: u8,
+    carry_losses_years: Option<u8>,
+    /// detected from the header line when omitted
+    #[clap(short, long, parse(try_from_str = ascii_delimiter))]
+    delimiter: Option<u8>,
+    /// skip invalid transactions instead of failing
+    #[clap(short, long)]
+    lenient: bool,
+    /// converts all transactions to this currency
+    #[clap(short, long)]
+    base_currency: Option<String>,
+    /// how the report is rounded to two decimals, truncate by default
+    #[clap(short, long, arg_enum)]
+    rounding: Option<RoundingArg>,
+    /// always prints two decimals, including trailing zeros
+    #[clap(short = 'x', long)]
+    fixed: bool,
+    /// fails on sells that would open a short position
+    #[clap(long)]
+    disallow_shorts: bool,
+    /// accounts for rows marked as cancelled or pending orders
+    #[clap(long)]
+    include_cancelled: bool,
+    /// drops the transactions of this order id, can be repeated
+    #[clap(long)]
+    exclude_order: Vec<String>,
+    /// for exports with only positive quantities, signs them by the value
+    #[clap(long)]
+    unsigned_quantities: bool,
+    /// fails on dates that are not DD-MM-YYYY instead of guessing them
+    #[clap(long)]
+    strict_dates: bool,
+    /// warns about rows whose total is not their value plus the fee
+    #[clap(long)]
+    check_totals: bool,
+    /// the currency of amounts without one in the file
+    #[clap(long)]
+    assume_currency: Option<String>,
+    /// reads the whole file and sorts it, for files in any order
+    #[clap(short, long)]
+    sort: bool,
+    /// adds the fees of buys to their cost basis
+    #[clap(long)]
+    capitalize_fees: bool,
+    /// folds the separate transaction cost rows into their trades
+    #[clap(long)]
+    fold_costs: bool,
+    /// drops reversal rows together with the transaction they reverse
+    #[clap(long)]
+    cancel_reversals: bool,
+    /// merges the fills of an order into one transaction
+    #[clap(long)]
+    aggregate_fills: bool,
+    /// how the cost of sold shares is determined, average by default
+    #[clap(long, arg_enum)]
+    cost_basis: Option<CostBasisArg>,
+    /// `degiro-pnl` follows the Profit & Loss overview of DeGiro
+    #[clap(long, arg_enum, default_value = "tax")]
+    style: StyleArg,
+    /// only reports the sells from this date on, as YYYY-MM-DD
+    #[clap(long)]
+    since: Option<NaiveDate>,
+    /// also prints the gains and losses of the year
+    #[clap(long)]
+    breakdown: bool,
+    /// writes the cumulative realized profit at the end of every day to this CSV file
+    #[clap(long, conflicts_with = "dir")]
+    equity_curve: Option<String>,
+    /// `json` includes the warnings, `csv` is the tax form of the year
+    #[clap(long, arg_enum, default_value = "text")]
+    format: FormatArg,
+    /// tax free gains per year, subtracted by the taxable report
+    #[clap(short = 'e', long)]
+    annual_exemption: Option<Money>,
+    /// warns about every sell that loses more than this
+    #[clap(long)]
+    warn_loss: Option<Money>,
+    /// decimal and grouping separators of the output, en by default
+    #[clap(long, arg_enum)]
+    locale: Option<LocaleArg>,
+    /// options used when they are not given as flags
+    #[clap(long, default_value = "degiro.toml")]
+    config: String,
+    #[clap(skip)]
+    currency_overrides: HashMap<String, String>,
 
     #[clap(subcommand)]
     args: Args,
@@ -23,25 +125,290 @@ struct Cli {
 enum Args {
     Adjusted,
     Unadjusted,
+    /// the adjusted profit minus the annual exemption
+    Taxable,
+}
+
+/// The options of the config file, flags take precedence
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    year: Option<i32>,
+    carry_losses_years: Option<u8>,
+    base_currency: Option<String>,
+    cost_basis: Option<CostBasisArg>,
+    rounding: Option<RoundingArg>,
+    locale: Option<LocaleArg>,
+    warn_loss: Option<Money>,
+    #[serde(default)]
+    fixed: bool,
+    #[serde(default)]
+    breakdown: bool,
+    /// ISINs mapped to the currency forced on their transactions
+    #[serde(default)]
+    currency_overrides: HashMap<String, String>,
+}
+
+impl Config {
+    // a missing file is an empty config
+    fn load(path: &str) -> anyhow::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => Ok(toml::from_str(&s)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Cli {
+    fn merge(mut self, config: Config) -> Self {
+        self.year = self.year.or(config.year);
+        self.carry_losses_years = self.carry_losses_years.or(config.carry_losses_years);
+        self.base_currency = self.base_currency.or(config.base_currency);
+        self.cost_basis = self.cost_basis.or(config.cost_basis);
+        self.rounding = self.rounding.or(config.rounding);
+        self.locale = self.locale.or(config.locale);
+        self.warn_loss = self.warn_loss.or(config.warn_loss);
+        self.fixed |= config.fixed;
+        self.breakdown |= config.breakdown;
+        self.currency_overrides = config.currency_overrides;
+        self
+    }
+}
+
+#[derive(Debug, ArgEnum, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum RoundingArg {
+    HalfUp,
+    HalfEven,
+    Truncate,
+}
+
+#[derive(Debug, ArgEnum, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum CostBasisArg {
+    Average,
+    Fifo,
+}
+
+impl From<CostBasisArg> for CostBasisMethod {
+    fn from(arg: CostBasisArg) -> Self {
+        match arg {
+            CostBasisArg::Average => CostBasisMethod::Average,
+            CostBasisArg::Fifo => CostBasisMethod::Fifo,
+        }
+    }
+}
+
+#[derive(Debug, ArgEnum, Clone)]
+enum StyleArg {
+    Tax,
+    DegiroPnl,
+}
+
+impl From<StyleArg> for ReportStyle {
+    fn from(arg: StyleArg) -> Self {
+        match arg {
+            StyleArg::Tax => ReportStyle::Tax,
+            StyleArg::DegiroPnl => ReportStyle::DegiroPnl,
+        }
+    }
+}
+
+#[derive(Debug, ArgEnum, Clone, PartialEq)]
+enum FormatArg {
+    Text,
+    Json,
+    Csv,
+    Html,
+    Toml,
+}
+
+#[derive(Debug, ArgEnum, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum LocaleArg {
+    En,
+    Nl,
+}
+
+impl From<LocaleArg> for Locale {
+    fn from(arg: LocaleArg) -> Self {
+        match arg {
+            LocaleArg::En => Locale::En,
+            LocaleArg::Nl => Locale::Nl,
+        }
+    }
+}
+
+impl From<Args> for Profit {
+    fn from(args: Args) -> Self {
+        match args {
+            Args::Adjusted => Profit::Adjusted,
+            Args::Unadjusted => Profit::Unadjusted,
+            Args::Taxable => Profit::Taxable,
+        }
+    }
+}
+
+impl From<RoundingArg> for Rounding {
+    fn from(arg: RoundingArg) -> Self {
+        match arg {
+            RoundingArg::HalfUp => Rounding::HalfUp,
+            RoundingArg::HalfEven => Rounding::HalfEven,
+            RoundingArg::Truncate => Rounding::Truncate,
+        }
+    }
+}
+
+// the csv reader splits on a single byte
+fn ascii_delimiter(s: &str) -> Result<u8, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(d), None) if d.is_ascii() => Ok(d as u8),
+        (Some(_), None) => Err(format!("`{}` is not an ASCII character", s)),
+        _ => Err(format!("`{}` is not a single character", s)),
+    }
+}
+
+type TransactionStream = Pin<Box<dyn Stream<Item = anyhow::Result<Transaction>>>>;
+
+// the portfolio of the file at `path` with the options of `cli`
+fn file_portfolio(cli: &Cli, path: &Path) -> anyhow::Result<Portfolio<TransactionStream>> {
+    let tr_stream = match cli.delimiter {
+        Some(d) => {
+            let file =
+                File::open(path).map_err(|e| anyhow!("cannot open {}: {}", path.display(), e))?;
+            CsvStream::with_delimiter(file, d)?
+        }
+        None => CsvStream::from_path(path)?,
+    };
+    let strictness = match cli.lenient {
+        true => Strictness::Lenient,
+        false => Strictness::Strict,
+    };
+    let mut tr_stream = tr_stream
+        .strictness(strictness)
+        .unsigned_quantities(cli.unsigned_quantities)
+        .strict_dates(cli.strict_dates);
+    if let Some(currency) = &cli.assume_currency {
+        tr_stream = tr_stream.assume_currency(currency);
+    }
+    #[cfg(feature = "source-hash")]
+    let source_hash = tr_stream.source_hash().to_string();
+    let mut tr_stream: TransactionStream = match cli.sort {
+        true => Box::pin(sorted(tr_stream)),
+        false => Box::pin(tr_stream),
+    };
+    if cli.cancel_reversals {
+        tr_stream = Box::pin(cancel_reversals(tr_stream));
+    }
+    if cli.fold_costs {
+        tr_stream = Box::pin(fold_costs(tr_stream));
+    }
+    if cli.aggregate_fills {
+        tr_stream = Box::pin(aggregate_fills(tr_stream));
+    }
+    let mut portfolio =
+        Portfolio::with_carry_losses(tr_stream, cli.carry_losses_years.unwrap_or(0))
+            .strictness(strictness)
+            .cost_basis(
+                cli.cost_basis
+                    .clone()
+                    .unwrap_or(CostBasisArg::Average)
+                    .into(),
+            )
+            .style(cli.style.clone().into())
+            .disallow_shorts(cli.disallow_shorts)
+            .include_cancelled(cli.include_cancelled)
+            .check_totals(cli.check_totals)
+            .capitalize_fees(cli.capitalize_fees)
+            .exclude_orders(cli.exclude_order.clone())
+            .currency_overrides(cli.currency_overrides.clone());
+    #[cfg(feature = "source-hash")]
+    {
+        portfolio = portfolio.source_hash(&source_hash);
+    }
+    if let Some(since) = cli.since {
+        portfolio = portfolio.since(since);
+    }
+    if let Some(exemption) = cli.annual_exemption.clone() {
+        portfolio = portfolio.annual_exemption(exemption);
+    }
+    if let Some(limit) = cli.warn_loss.clone() {
+        portfolio = portfolio.warn_loss(limit);
+    }
+    if let Some(currency) = &cli.base_currency {
+        portfolio = portfolio.base_currency(currency);
+    }
+    Ok(portfolio)
 }
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
-    let cli = Cli::parse();
-    let f = File::open(cli.file).unwrap();
-    let tr_stream = CsvStream::new(f).unwrap();
-    let portfolio = Portfolio::with_carry_losses(tr_stream, cli.carry_losses_years);
+    if let Err(e) = run(Cli::parse()).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
 
-    let profits = portfolio.report(cli.year).await.unwrap();
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    let config = Config::load(&cli.config)
+        .map_err(|e| anyhow!("invalid config file {}: {}", cli.config, e))?;
+    let cli = cli.merge(config);
+    let year = cli
+        .year
+        .ok_or_else(|| anyhow!("the year is missing from the flags and the config file"))?;
+    if let (Some(curve_path), Some(path)) = (&cli.equity_curve, &cli.file) {
+        let curve = file_portfolio(&cli, Path::new(path))?
+            .equity_curve()
+            .await
+            .map_err(|e| anyhow!("{}: {}", path, e))?;
+        let mut curve_file =
+            File::create(curve_path).map_err(|e| anyhow!("{}: {}", curve_path, e))?;
+        write_equity_curve(&curve, &mut curve_file)?;
+    }
+    let (profits, files) = match &cli.dir {
+        Some(dir) => report_dir(dir, year, |path| file_portfolio(&cli, path)).await?,
+        None => {
+            let path = PathBuf::from(cli.file.clone().unwrap_or_default());
+            let report = file_portfolio(&cli, &path)?
+                .report(year)
+                .await
+                .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+            (report.clone(), vec![(path, report)])
+        }
+    };
+    if cli.format != FormatArg::Json {
+        for warning in profits.warnings() {
+            eprintln!("warning: {}", warning);
+        }
+    }
 
-    let report = match cli.args {
-        Args::Adjusted => profits.adjusted_profit().unwrap(),
-        Args::Unadjusted => profits.profit().unwrap(),
+    let rounding: Rounding = cli.rounding.unwrap_or(RoundingArg::Truncate).into();
+    let text = Text {
+        profit: cli.args.clone().into(),
+        rounding,
+        locale: cli.locale.unwrap_or(LocaleArg::En).into(),
+        fixed: cli.fixed,
+        breakdown: cli.breakdown,
+    };
+    let renderer: Box<dyn ReportRenderer> = match cli.format {
+        FormatArg::Text => Box::new(text.clone()),
+        FormatArg::Json => Box::new(Json),
+        FormatArg::Csv => Box::new(Csv),
+        FormatArg::Html => Box::new(Html { rounding }),
+        FormatArg::Toml => Box::new(Toml),
     };
 
-    std::io::stdout()
-        .write(format!("report: {}", report).as_bytes())
-        .unwrap();
+    let mut out = std::io::stdout();
+    if cli.dir.is_some() && cli.format == FormatArg::Text {
+        for (path, report) in &files {
+            let profit = text.profit.of(report)?;
+            writeln!(out, "{}: {}", path.display(), text.amount(&profit))?;
+        }
+    }
+    renderer.render(&profits, &mut out)?;
+    Ok(())
 }
@@ -2,9 +2,10 @@ use decimal::d128;
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::de::Visitor;
-use serde::{de, Deserialize, Deserializer, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::fmt::{Debug, Display, Formatter};
+use std::ops::Neg;
 use std::str::FromStr;
 
 #[non_exhaustive]
@@ -30,7 +31,28 @@ impl Display for Error {
 
 impl std::error::Error for Error {}
 
-#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+/// How an amount is rounded to a number of decimals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// to the nearest, halves away from zero
+    HalfUp,
+    /// to the nearest, halves to the even neighbour
+    HalfEven,
+    /// towards zero
+    Truncate,
+}
+
+/// How amounts are written in the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    /// `1234.56`
+    #[default]
+    En,
+    /// `1.234,56`
+    Nl,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Money {
     pub(crate) amount: d128,
     pub(crate) currency: Option<String>,
@@ -38,16 +60,20 @@ pub struct Money {
 
 impl Display for Money {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let res = write!(f, "amount: {}", self.amount);
-
-        if let Some(currency) = &self.currency {
-            return write!(f, "currency: {}", currency);
-        }
-        res
+        f.write_str(&self.format(Locale::En))
     }
 }
 
 impl Money {
+    /// like `Display`, with the separators of `locale`
+    pub fn format(&self, locale: Locale) -> String {
+        let amount = format_amount(self.amount, locale);
+        match &self.currency {
+            Some(currency) => format!("amount: {} currency: {}", amount, currency),
+            None => format!("amount: {}", amount),
+        }
+    }
+
     pub fn new(amount: d128) -> Self {
         Self {
             amount,
@@ -62,14 +88,27 @@ impl Money {
         }
     }
 
+    pub fn amount(&self) -> d128 {
+        self.amount
+    }
+
+    /// the lowercase currency code, if any
+    pub fn currency(&self) -> Option<&str> {
+        self.currency.as_deref()
+    }
+
+    /// fails when the currencies differ, except that a zero without a currency,
+    /// like `Money::default()`, takes the currency of `rhs`
+    /// so a sum can start from the default
     pub fn add(&mut self, rhs: &Self) -> Result<(), Error> {
-        self.validate(&rhs)?;
+        self.currency = self.currency_with(rhs)?;
         self.amount += rhs.amount;
         Ok(())
     }
 
+    /// like `add`
     pub fn sub(&mut self, rhs: &Self) -> Result<(), Error> {
-        self.validate(&rhs)?;
+        self.currency = self.currency_with(rhs)?;
         self.amount -= rhs.amount;
         Ok(())
     }
@@ -85,8 +124,47 @@ impl Money {
     }
 
     pub fn truncate_trailing_zeros(&self) -> Self {
+        let mut amount = self.amount.reduce();
+        // reducing `100` gives `1E+2`
+        if (amount % d128::from(1)).is_zero() {
+            amount = amount.quantize(d128::from(1));
+        }
+        Self {
+            amount,
+            currency: self.currency.clone(),
+        }
+    }
+
+    /// rounds to at most `decimals` decimals and drops trailing zeros
+    pub fn round(&self, decimals: u32, rounding: Rounding) -> Self {
+        let scale = d128::from(10_i64.pow(decimals));
+        let scaled = self.amount * scale;
+        let fraction = scaled % d128::from(1);
+        let truncated = scaled - fraction;
+
+        let rounded = match rounding {
+            Rounding::Truncate => truncated,
+            Rounding::HalfEven => scaled.quantize(d128::from(1)),
+            Rounding::HalfUp if fraction.abs() >= d128!(0.5) => match scaled.is_negative() {
+                true => truncated - d128::from(1),
+                false => truncated + d128::from(1),
+            },
+            Rounding::HalfUp => truncated,
+        };
+
+        Self {
+            amount: rounded / scale,
+            currency: self.currency.clone(),
+        }
+        .truncate_trailing_zeros()
+    }
+
+    /// pads with zeros to exactly `decimals` decimals,
+    /// more decimals are rounded half-even
+    pub fn fixed(&self, decimals: u32) -> Self {
+        let quantum = d128::from(1).scaleb(d128::from(-(decimals as i32)));
         Self {
-            amount: self.amount.reduce(),
+            amount: self.amount.quantize(quantum),
             currency: self.currency.clone(),
         }
     }
@@ -98,16 +176,53 @@ impl Money {
         }
     }
 
+    /// sets the currency when there is none
+    pub fn assume_currency(&mut self, currency: &str) {
+        if self.currency.is_none() {
+            self.currency = Some(currency.to_lowercase());
+        }
+    }
+
+    /// flips the sign, keeping the currency
+    pub fn negate(&self) -> Self {
+        Self {
+            amount: -self.amount,
+            currency: self.currency.clone(),
+        }
+    }
+
     pub fn is_negative(&self) -> bool {
         self.amount.is_negative()
     }
 
-    fn validate(&self, rhs: &Self) -> Result<(), Error> {
+    /// converts to `currency` given how many units
+    /// of the current currency one unit of `currency` buys
+    pub fn convert(&self, currency: &str, rate: d128) -> Self {
+        Self {
+            amount: self.amount / rate,
+            currency: Some(currency.to_lowercase()),
+        }
+    }
+
+    // the currency of the result of `add` or `sub`,
+    // a zero without a currency is compatible with any currency
+    fn currency_with(&self, rhs: &Self) -> Result<Option<String>, Error> {
         let currency = rhs.currency.as_ref().map(|c| c.to_lowercase());
-        if self.currency != currency {
-            return Err(Error::Currency(self.currency.clone(), currency));
+        if self.currency == currency || (currency.is_none() && rhs.amount.is_zero()) {
+            return Ok(self.currency.clone());
         }
-        Ok(())
+        if self.currency.is_none() && self.amount.is_zero() {
+            return Ok(currency);
+        }
+        Err(Error::Currency(self.currency.clone(), currency))
+    }
+}
+
+impl Neg for Money {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        self.negate()
     }
 }
 
@@ -120,10 +235,40 @@ impl FromStr for Money {
             static ref RE2: Regex = Regex::new(r"^(?i)([-]?[0-9,.]+)\s*([a-z]+)\s*$").unwrap();
         }
 
-        for cap in RE1.captures_iter(s) {
-            let currency = cap[0].to_lowercase();
+        // quotes around the currency or the whole value are left over from the csv
+        let unquoted: String = s.chars().filter(|c| *c != '"' && *c != '\'').collect();
+        let s = unquoted.trim();
+
+        // accounting exports put negatives in parentheses, like `(10)` or `EUR (10)`
+        if let (Some(open), Some(close)) = (s.find('('), s.rfind(')')) {
+            if open < close {
+                let inner = format!("{} {} {}", &s[..open], &s[open + 1..close], &s[close + 1..]);
+                let money = Self::from_str(&inner)?;
+                if money.amount.is_negative() {
+                    return Err(format!("invalid input: `{}`", s));
+                }
+                return Ok(money.negate());
+            }
+        }
+
+        // a currency symbol before or after the amount, like `€100` or `100€`
+        for (symbol, code) in &[('€', "eur"), ('$', "usd"), ('£', "gbp")] {
+            if let Some(amount) = s.strip_prefix(*symbol).or_else(|| s.strip_suffix(*symbol)) {
+                let amount = amount.trim();
+                let amount =
+                    d128::from_str(amount).map_err(|_| format!("invalid amount: `{}`", amount))?;
+
+                return Ok(Self {
+                    amount,
+                    currency: Some(code.to_string()),
+                });
+            }
+        }
+
+        if let Some(cap) = RE1.captures(s) {
+            let currency = cap[1].to_lowercase();
             let amount =
-                d128::from_str(&cap[1]).map_err(|_| format!("invalid amount: `{}`", &cap[1]))?;
+                d128::from_str(&cap[2]).map_err(|_| format!("invalid amount: `{}`", &cap[2]))?;
 
             return Ok(Self {
                 amount,
@@ -131,10 +276,10 @@ impl FromStr for Money {
             });
         }
 
-        for cap in RE2.captures_iter(s) {
-            let currency = cap[1].to_lowercase();
+        if let Some(cap) = RE2.captures(s) {
+            let currency = cap[2].to_lowercase();
             let amount =
-                d128::from_str(&cap[0]).map_err(|_| format!("invalid amount: `{}`", &cap[1]))?;
+                d128::from_str(&cap[1]).map_err(|_| format!("invalid amount: `{}`", &cap[1]))?;
 
             return Ok(Self {
                 amount,
@@ -151,6 +296,78 @@ impl FromStr for Money {
     }
 }
 
+fn format_amount(amount: d128, locale: Locale) -> String {
+    let s = amount.to_string();
+    // exponents and special values are left alone
+    if locale == Locale::En || s.contains(|c: char| !c.is_ascii_digit() && c != '.' && c != '-') {
+        return s;
+    }
+    let (sign, digits) = match s.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", s.as_str()),
+    };
+    let (int, fraction) = match digits.split_once('.') {
+        Some((int, fraction)) => (int, Some(fraction)),
+        None => (digits, None),
+    };
+
+    let mut res = sign.to_string();
+    for (i, c) in int.chars().enumerate() {
+        if i > 0 && (int.len() - i) % 3 == 0 {
+            res.push('.');
+        }
+        res.push(c);
+    }
+    if let Some(fraction) = fraction {
+        res.push(',');
+        res.push_str(fraction);
+    }
+    res
+}
+
+// parses `1.234,56`, `1,234.56`, `1,0923` and `1.0923` alike:
+// with both separators the last one is decimal,
+// a single one is decimal unless it repeats.
+// A currency code around the number is ignored
+pub(crate) fn parse_decimal(s: &str) -> Result<d128, String> {
+    let number = s
+        .trim()
+        .trim_matches(|c: char| c.is_alphabetic() || c.is_whitespace());
+    let decimal = match (number.rfind(','), number.rfind('.')) {
+        (Some(c), Some(p)) => Some(c.max(p)),
+        (Some(c), None) if number.matches(',').count() == 1 => Some(c),
+        (None, Some(p)) if number.matches('.').count() == 1 => Some(p),
+        _ => None,
+    };
+
+    let normalized: String = number
+        .char_indices()
+        .filter_map(|(i, c)| match c {
+            ',' | '.' if Some(i) == decimal => Some('.'),
+            ',' | '.' => None,
+            c => Some(c),
+        })
+        .collect();
+
+    d128::from_str(&normalized)
+        .ok()
+        .filter(|d| !d.is_nan())
+        .ok_or_else(|| format!("invalid number: `{}`", s))
+}
+
+// serializes to the same `<amount> <currency>` form that `FromStr` parses
+impl Serialize for Money {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match &self.currency {
+            Some(currency) => serializer.collect_str(&format_args!("{} {}", self.amount, currency)),
+            None => serializer.collect_str(&self.amount),
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for Money {
     fn deserialize<D>(deserializer: D) -> Result<Money, D::Error>
     where
@@ -176,3 +393,115 @@ impl<'de> Visitor<'de> for MoneyVisitor {
         Money::from_str(value).map_err(|e| de::Error::custom(e))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::money::{parse_decimal, Locale, Money};
+    use decimal::d128;
+
+    #[test]
+    fn currency_codes() {
+        for s in &[
+            "EUR 100",
+            "100 EUR",
+            "eur100",
+            " \"EUR\" 100 ",
+            "100 'EUR'\t",
+        ] {
+            let money = s.parse::<Money>().unwrap();
+
+            assert_eq!(money.currency.as_deref(), Some("eur"), "{}", s);
+            assert_eq!(money.amount, d128!(100), "{}", s);
+        }
+    }
+
+    #[test]
+    fn currency_symbols() {
+        let eur = Money::with_currency(d128::from(100), "EUR".to_string());
+
+        assert_eq!("€100".parse::<Money>().unwrap(), eur);
+        assert_eq!("100€".parse::<Money>().unwrap(), eur);
+        assert_eq!(
+            "$100".parse::<Money>().unwrap(),
+            Money::with_currency(d128::from(100), "USD".to_string())
+        );
+        assert_eq!(
+            "£50".parse::<Money>().unwrap(),
+            Money::with_currency(d128::from(50), "GBP".to_string())
+        );
+    }
+
+    #[test]
+    fn locale_format() {
+        let amount = Money::new(d128!(-1234567.56));
+
+        assert_eq!(amount.format(Locale::En), "amount: -1234567.56");
+        assert_eq!(amount.format(Locale::Nl), "amount: -1.234.567,56");
+        assert_eq!(Money::new(d128!(123)).format(Locale::Nl), "amount: 123");
+
+        let eur = Money::with_currency(d128!(1234.5), "EUR".to_string());
+        assert_eq!(eur.format(Locale::Nl), "amount: 1.234,5 currency: eur");
+        assert_eq!(eur.to_string(), "amount: 1234.5 currency: eur");
+    }
+
+    #[test]
+    fn accessors() {
+        let money = Money::with_currency(d128!(12.5), "EUR".to_string());
+
+        assert_eq!(money.amount(), d128!(12.5));
+        assert_eq!(money.currency(), Some("eur"));
+        assert_eq!(Money::new(d128!(1)).currency(), None);
+    }
+
+    #[test]
+    fn untagged_zero() {
+        let eur = Money::with_currency(d128!(10), "EUR".to_string());
+        let usd = Money::with_currency(d128!(10), "USD".to_string());
+
+        let mut sum = Money::default();
+        sum.add(&eur).unwrap();
+        assert_eq!(sum, eur);
+        sum.sub(&Money::default()).unwrap();
+        assert_eq!(sum, eur);
+        assert!(sum.add(&usd).is_err());
+
+        let mut untagged = Money::new(d128!(1));
+        assert!(untagged.add(&eur).is_err());
+        assert!(eur.clone().add(&Money::new(d128!(1))).is_err());
+        assert_eq!(untagged, Money::new(d128!(1)));
+    }
+
+    #[test]
+    fn negate() {
+        let positive = Money::with_currency(d128!(12.5), "EUR".to_string());
+        let negative = Money::with_currency(d128!(-12.5), "EUR".to_string());
+
+        assert_eq!(positive.negate(), negative);
+        assert_eq!(-negative, positive);
+    }
+
+    #[test]
+    fn parenthesized_negatives() {
+        let money = "(123.45)".parse::<Money>().unwrap();
+        assert_eq!(money, Money::new(d128!(-123.45)));
+
+        for s in &["EUR (10)", "(EUR 10)", "(10) EUR", "(10 EUR)"] {
+            let money = s.parse::<Money>().unwrap();
+            assert_eq!(money.currency(), Some("eur"), "{}", s);
+            assert_eq!(money.amount(), d128!(-10), "{}", s);
+        }
+
+        assert!("(-10)".parse::<Money>().is_err());
+    }
+
+    #[test]
+    fn decimal_separators() {
+        assert_eq!(parse_decimal("1,0923").unwrap(), d128!(1.0923));
+        assert_eq!(parse_decimal("1.0923").unwrap(), d128!(1.0923));
+        assert_eq!(parse_decimal("1.234,56").unwrap(), d128!(1234.56));
+        assert_eq!(parse_decimal("1,234.56").unwrap(), d128!(1234.56));
+        assert_eq!(parse_decimal("1,234,567").unwrap(), d128!(1234567));
+        assert_eq!(parse_decimal(" 1,0923 USD").unwrap(), d128!(1.0923));
+        assert!(parse_decimal("rate").is_err());
+    }
+}
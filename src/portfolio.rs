@@ -1,322 +1,4392 @@
-use crate::money::Error;
-use crate::{Money, Transaction, TransactionType};
+use crate::money::{Error, Rounding};
+use crate::{Money, Strictness, Transaction, TransactionType};
 use anyhow::anyhow;
-use chrono::Datelike;
-use futures::stream::Stream;
-use futures::{pin_mut, StreamExt};
-use std::collections::HashMap;
+use chrono::{Datelike, NaiveDate};
+use decimal::d128;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::stream::{self, PollNext, Stream};
+use futures::{future, pin_mut, StreamExt};
+use log::warn;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fmt::{Display, Formatter};
 use std::pin::Pin;
+use std::task::Poll;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct Report {
     profits: HashMap<i32, (Money, Money)>,
-    years_carry_losses: u8,
+    // realized profit per year and ISIN
+    product_profits: HashMap<i32, HashMap<String, Money>>,
+    // cost basis of the sold shares per year and ISIN
+    product_costs: HashMap<i32, HashMap<String, Money>>,
+    fees: HashMap<i32, Money>,
+    // the values net of fees by year and currency, see `net_cash_flow`
+    cash_flows: HashMap<(i32, Option<String>), Money>,
+    // the absolute values of the trades by year and currency, see `turnover`
+    turnover: HashMap<(i32, Option<String>), Money>,
+    accrued_interest: HashMap<i32, Money>,
+    // days held times quantity and the quantity of the closed lots per ISIN
+    holding_days: HashMap<String, (i64, i64)>,
+    closed_lots: Vec<ClosedLot>,
+    // the ISIN, date and profit of every sell by year, see `extremes`
+    sells: HashMap<i32, Vec<Sale>>,
+    // the cost of the positions still open at the end of the report per ISIN
+    open_costs: HashMap<String, Money>,
+    // ISINs mapped to their category, see `Portfolio::categories`
+    categories: HashMap<String, String>,
+    // quantity weighted exchange rates and the quantity per year and local currency
+    exchange_rates: HashMap<(i32, String), (d128, d128)>,
+    // realized profit per year and ISIN in the currency of the ISIN
+    local_profits: HashMap<i32, HashMap<String, Money>>,
+    carry_loss_policy: CarryLossPolicy,
+    annual_exemption: Option<Money>,
+    source_hash: Option<String>,
+    processed: Processed,
+    audit: Vec<AuditEntry>,
     year: i32,
+    warnings: Vec<Warning>,
+    currencies: BTreeSet<String>,
 }
 
-impl Report {
-    ///returns the total profits
-    pub fn profit(&self) -> anyhow::Result<Money> {
-        let profit = self.profits.get(&self.year).ok_or(anyhow!(
-            "cannot find data for year: {} profits: {:#?}",
-            self.year,
-            self.profits
-        ))?;
+/// The realized gains and losses of a year
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct YearProfit {
+    pub gains: Money,
+    /// as a negative amount
+    pub losses: Money,
+}
 
-        let mut res = profit.0.clone();
-        res.add(&profit.1)?;
-        Ok(res)
+/// A buy or sell with the position before and after it
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub isin: String,
+    pub order_id: String,
+    pub date: NaiveDate,
+    pub r#type: TransactionType,
+    pub before: Position,
+    pub after: Position,
+    /// the realized profit of a sell
+    pub profit: Option<Money>,
+}
+
+/// The state of a position in `AuditEntry`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Position {
+    pub qty: isize,
+    pub total: Money,
+    pub avg: Money,
+}
+
+/// A bought lot or part of it closed by a sell
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClosedLot {
+    pub isin: String,
+    pub bought: NaiveDate,
+    pub sold: NaiveDate,
+    pub qty: isize,
+    pub cost: Money,
+    /// the share of the sell value for the lot
+    pub proceeds: Money,
+}
+
+impl ClosedLot {
+    /// returns `(proceeds / cost) ^ (365 / days) - 1`, as a fraction.
+    /// None for lots without a cost or sold the day they were bought
+    pub fn annualized_return(&self) -> Option<d128> {
+        let days = (self.sold - self.bought).num_days();
+        if days <= 0 || self.cost.amount.is_zero() {
+            return None;
+        }
+        let growth = self.proceeds.amount / self.cost.amount;
+        Some(growth.pow(d128::from(365) / d128::from(days)) - d128!(1))
     }
+}
 
-    /// returns the profits minus the carry over losses
-    /// from previous years
-    pub fn adjusted_profit(&self) -> Result<Money, Error> {
-        let mut prf: Vec<(i32, (Money, Money))> = self
-            .profits
-            .iter()
-            .filter(|(k, _)| **k >= self.year - self.years_carry_losses as i32)
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
+/// Tax rates for `Report::estimate_tax`
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaxSchedule {
+    // the amount a rate applies above, ascending, with the rate as a fraction
+    brackets: Vec<(d128, d128)>,
+}
 
-        prf.sort_by(|a, b| a.0.cmp(&b.0));
+impl TaxSchedule {
+    /// one rate for all of the profit, `0.3` is 30%
+    pub fn flat(rate: d128) -> Self {
+        Self {
+            brackets: vec![(d128::from(0), rate)],
+        }
+    }
 
-        let mut start = false;
-        let mut total = Money::default();
-        let mut profit = Money::default();
+    /// every rate applies to the part of the profit above its amount
+    /// and up to the amount of the next bracket
+    pub fn brackets(mut brackets: Vec<(d128, d128)>) -> Self {
+        brackets.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        Self { brackets }
+    }
+}
 
-        for (year, prof) in prf {
-            let mut add = prof.0.clone();
-            add.add(&prof.1)?;
-            if year == self.year {
-                profit = add;
-                break;
-            }
-            if !start && add.is_negative() {
-                start = true;
-            }
+/// How many transactions were read to produce a report
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Processed {
+    pub buys: usize,
+    pub sells: usize,
+    /// everything that did not change a position
+    pub skipped: usize,
+}
 
-            if start {
-                total.add(&add)?;
+/// A problem in the order of the transactions of a single ISIN
+#[derive(Clone, Debug, PartialEq)]
+pub enum Issue {
+    /// the first transaction of the ISIN is a sell
+    SellBeforeBuy {
+        isin: String,
+        order_id: String,
+        date: NaiveDate,
+    },
+    /// a sell of more than was bought, which leaves a negative cost basis
+    NegativeCostBasis {
+        isin: String,
+        order_id: String,
+        date: NaiveDate,
+    },
+    /// a buy or sell without an order id to correlate it with costs and fills
+    BlankOrderId { isin: String, date: NaiveDate },
+}
 
-                if !total.is_negative() {
-                    total = Money::default();
-                    start = false;
-                }
-            }
-        }
+/// How the cost of the shares a sell closes is determined
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum CostBasisMethod {
+    /// the average price of the position
+    #[default]
+    Average,
+    /// the oldest buys are sold first
+    Fifo,
+    /// the buy order ids every sell order id closes first,
+    /// the remainder and other sells close the oldest buys
+    Specific(HashMap<String, Vec<String>>),
+}
 
-        profit.add(&total)?;
-        let trunc = profit.truncate_trailing_zeros();
+/// The conventions of the report
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReportStyle {
+    /// transaction costs are only tallied, see `Report::total_fees`
+    #[default]
+    Tax,
+    /// like the Profit & Loss overview of DeGiro: average cost,
+    /// with the costs added to the basis of buys and taken from the proceeds of sells.
+    /// `Report::profit_by_product` gives its per product totals
+    DegiroPnl,
+}
 
-        if trunc.is_negative() {
-            return Ok(Money{ amount: Default::default(), currency: trunc.currency });
-        }
-        Ok(trunc)
-    }
+/// How dividends paid in shares (DRIP) are accounted for, see `Transaction::is_drip`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DripTreatment {
+    /// the shares are bought for nothing, lowering the average cost.
+    /// The dividend is then taxed as part of the gain when they are sold,
+    /// unless it is also declared as income, which would tax it twice
+    #[default]
+    ZeroCost,
+    /// the rows are skipped, for when the dividend is declared as income
+    /// and the shares are accounted for elsewhere
+    Exclude,
 }
 
-// The Stream is expected to produce transactions in order
-// from oldest to newest
-pub struct Portfolio<S: Stream<Item = anyhow::Result<Transaction>>> {
-    tr_stream: S,
-    years_carry_losses: u8,
+/// How losses offset the gains of other years
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CarryLossPolicy {
+    /// years a loss offsets later gains for, `None` is without a limit
+    pub forward_years: Option<u8>,
+    /// years a loss offsets earlier gains for
+    pub back_years: Option<u8>,
 }
 
-impl<S: Stream<Item = anyhow::Result<Transaction>>> Portfolio<S> {
-    pub fn new(tr_stream: S) -> Self {
+impl CarryLossPolicy {
+    /// losses are carried forward for `years` and never back
+    pub fn flat(years: u8) -> Self {
         Self {
-            tr_stream,
-            years_carry_losses: 0,
+            forward_years: Some(years),
+            back_years: None,
         }
     }
+}
+
+impl Default for CarryLossPolicy {
+    fn default() -> Self {
+        Self::flat(0)
+    }
+}
 
-    pub fn with_carry_losses(tr_stream: S, years_carry_losses: u8) -> Self {
-        Self {
-            tr_stream,
-            years_carry_losses,
+/// Something in the transactions that does not stop the report,
+/// but may make it wrong
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", content = "detail", rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum Warning {
+    UnmatchedSell(UnmatchedSell),
+    /// a transaction skipped in lenient mode, with the reason
+    Skipped(String),
+    /// the currencies found without a base currency to convert them to
+    MixedCurrencies(Vec<String>),
+    /// the signs of quantity and value disagree, the value decided
+    SignMismatch {
+        isin: String,
+        order_id: String,
+    },
+    /// the input had no transactions at all, so every profit is zero
+    NoTransactions,
+    /// years without transactions between years with transactions,
+    /// which may be a missing export file
+    MissingYears(Vec<i32>),
+    /// the total of the row is not its value plus the fee,
+    /// see `Portfolio::check_totals`
+    TotalMismatch {
+        isin: String,
+        order_id: String,
+        total: Money,
+        expected: Money,
+    },
+    /// a sell lost more than the limit of `Portfolio::warn_loss`
+    LargeLoss {
+        isin: String,
+        date: NaiveDate,
+        loss: Money,
+    },
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnmatchedSell(sell) => write!(
+                f,
+                "sell without matching buys isin: {} order: {} date: {} missing: {}",
+                sell.isin, sell.order_id, sell.date, sell.missing
+            ),
+            Self::Skipped(reason) => write!(f, "skipped: {}", reason),
+            Self::MixedCurrencies(currencies) => {
+                write!(f, "transactions in multiple currencies: {:?}", currencies)
+            }
+            Self::SignMismatch { isin, order_id } => write!(
+                f,
+                "quantity and value signs disagree isin: {} order: {}",
+                isin, order_id
+            ),
+            Self::NoTransactions => f.write_str("no transactions in the input"),
+            Self::MissingYears(years) => write!(f, "no transactions in the years: {:?}", years),
+            Self::TotalMismatch {
+                isin,
+                order_id,
+                total,
+                expected,
+            } => write!(
+                f,
+                "total disagrees with value and fee isin: {} order: {} total: {} expected: {}",
+                isin, order_id, total, expected
+            ),
+            Self::LargeLoss { isin, date, loss } => write!(
+                f,
+                "loss over the limit isin: {} date: {} loss: {}",
+                isin, date, loss
+            ),
         }
     }
+}
 
-    fn calc_trans_profit(tr: &Transaction, entry: &State) -> Result<Money, Error> {
-        let mut avg_price = entry.avg.clone();
-        avg_price.mul(tr.quantity)?;
+/// A sell of more shares than the recorded buys hold,
+/// usually because they were bought before the period the file covers.
+/// The profit of such a sell is too high.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UnmatchedSell {
+    pub isin: String,
+    pub order_id: String,
+    pub date: NaiveDate,
+    /// the sold quantity without matching buys
+    pub missing: isize,
+}
 
-        let mut local_profit = tr.value.clone();
-        let abs_avg = avg_price.abs();
-        local_profit.sub(&abs_avg)?;
-        Ok(local_profit)
+impl Report {
+    /// returns everything noticed while processing the transactions, in order
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
     }
 
-    pub async fn report(self, year: i32) -> anyhow::Result<Report> {
-        let tr_peek = self.tr_stream.peekable();
-        pin_mut!(tr_peek);
-        let mut tr_pin: Pin<&mut _> = tr_peek;
+    pub fn unmatched_sells(&self) -> Vec<UnmatchedSell> {
+        self.warnings
+            .iter()
+            .filter_map(|w| match w {
+                Warning::UnmatchedSell(sell) => Some(sell.clone()),
+                _ => None,
+            })
+            .collect()
+    }
 
-        let mut state_map: HashMap<String, State> = HashMap::new();
-        let mut profits = HashMap::new();
+    /// returns the realized profit of every ISIN in the year, sorted by ISIN
+    pub fn profit_by_product(&self) -> Vec<(String, Money)> {
+        let mut products: Vec<(String, Money)> = self
+            .product_profits
+            .get(&self.year)
+            .map(|p| p.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default();
+        products.sort_by(|a, b| a.0.cmp(&b.0));
+        products
+    }
 
-        while let Some(tr) = tr_pin.as_mut().next().await {
-            let tr = tr?;
-            if let Some(next_tr) = tr_pin.as_mut().peek().await {
-                match next_tr {
-                    Ok(nt) => {
-                        assert!(tr.date <= nt.date, "tr: {:#?} next: {:#?}", tr, next_tr);
-                    }
-                    Err(e) => return Err(anyhow!("{}", e)),
-                }
-            }
+    /// adds the profits, costs and counts of `other`,
+    /// such as the report of another account.
+    /// The year and the carry loss policy stay those of `self`
+    pub fn merge(&mut self, other: Report) -> Result<(), Error> {
+        for (year, (gains, losses)) in other.profits {
+            let profit = self.profits.entry(year).or_default();
+            profit.0.add(&gains)?;
+            profit.1.add(&losses)?;
+        }
+        for (year, products) in other.product_profits {
+            merge_products(self.product_profits.entry(year).or_default(), products)?;
+        }
+        for (year, products) in other.product_costs {
+            merge_products(self.product_costs.entry(year).or_default(), products)?;
+        }
+        for (year, products) in other.local_profits {
+            merge_products(self.local_profits.entry(year).or_default(), products)?;
+        }
+        for (year, fees) in other.fees {
+            self.fees.entry(year).or_default().add(&fees)?;
+        }
+        for (key, flow) in other.cash_flows {
+            self.cash_flows.entry(key).or_default().add(&flow)?;
+        }
+        for (key, traded) in other.turnover {
+            self.turnover.entry(key).or_default().add(&traded)?;
+        }
+        for (year, interest) in other.accrued_interest {
+            self.accrued_interest
+                .entry(year)
+                .or_default()
+                .add(&interest)?;
+        }
+        for (key, (weighted, quantity)) in other.exchange_rates {
+            let sum = self.exchange_rates.entry(key).or_default();
+            sum.0 += weighted;
+            sum.1 += quantity;
+        }
+        for (isin, (days, qty)) in other.holding_days {
+            let holding = self.holding_days.entry(isin).or_default();
+            holding.0 += days;
+            holding.1 += qty;
+        }
+        merge_products(&mut self.open_costs, other.open_costs)?;
+        self.categories.extend(other.categories);
+        self.closed_lots.extend(other.closed_lots);
+        for (year, sells) in other.sells {
+            self.sells.entry(year).or_default().extend(sells);
+        }
+        self.processed.buys += other.processed.buys;
+        self.processed.sells += other.processed.sells;
+        self.processed.skipped += other.processed.skipped;
+        self.audit.extend(other.audit);
+        self.warnings.extend(other.warnings);
+        self.currencies.extend(other.currencies);
+        Ok(())
+    }
 
-            match tr.r#type() {
-                TransactionType::Buy => {
-                    let entry = state_map.entry(tr.isin).or_insert(Default::default());
-                    entry.total.add(&tr.value.abs())?;
-                    entry.qty += tr.quantity;
+    /// returns the average days the closed lots of every ISIN were held,
+    /// weighted by quantity and sorted by ISIN
+    pub fn avg_holding_period(&self) -> Vec<(String, d128)> {
+        let mut periods: Vec<(String, d128)> = self
+            .holding_days
+            .iter()
+            .filter(|(_, (_, qty))| *qty != 0)
+            .map(|(isin, (days, qty))| (isin.clone(), d128::from(*days) / d128::from(*qty)))
+            .collect();
+        periods.sort_by(|a, b| a.0.cmp(&b.0));
+        periods
+    }
+
+    /// returns the realized profit of every ISIN in the year
+    /// in its local currency, sorted by ISIN.
+    /// Empty unless `Portfolio::local_profits` is set
+    pub fn local_profit_by_product(&self) -> Vec<(String, Money)> {
+        let mut profits: Vec<(String, Money)> = self
+            .local_profits
+            .get(&self.year)
+            .map(|p| p.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default();
+        profits.sort_by(|a, b| a.0.cmp(&b.0));
+        profits
+    }
 
-                    let mut avg_price = entry.total.clone();
-                    avg_price.div(entry.qty)?;
+    /// returns the cost of the positions still open at the end of the report,
+    /// short positions count negatively at the price they were opened at
+    pub fn open_cost_basis(&self) -> Result<Money, Error> {
+        let mut total = Money::default();
+        for cost in self.open_costs.values() {
+            total.add(cost)?;
+        }
+        Ok(total)
+    }
 
-                    entry.avg = avg_price;
-                }
-                TransactionType::Sell => {
-                    assert_ne!(tr.quantity, 0);
-                    let entry = state_map.get_mut(&tr.isin).unwrap();
-                    let local_profit = Self::calc_trans_profit(&tr, &entry)?;
-                    let profit = profits
-                        .entry(tr.date.year())
-                        .or_insert((Money::default(), Money::default()));
+    /// returns the average exchange rate of the transactions in `currency`
+    /// during `year`, weighted by quantity
+    pub fn avg_exchange_rate(&self, year: i32, currency: &str) -> Option<d128> {
+        let (weighted, quantity) = self.exchange_rates.get(&(year, currency.to_lowercase()))?;
+        if quantity.is_zero() {
+            return None;
+        }
+        Some(*weighted / *quantity)
+    }
 
-                    if local_profit.is_negative() {
-                        profit.1.add(&local_profit)?;
-                    } else {
-                        profit.0.add(&local_profit)?;
-                    }
+    /// returns every lot closed by a sell, in the order of the sells
+    pub fn closed_lots(&self) -> &[ClosedLot] {
+        &self.closed_lots
+    }
 
-                    assert!(!tr.value.is_negative());
-                    entry.total.sub(&tr.value)?;
-                    entry.qty += tr.quantity;
-                }
+    /// returns the annualized return of the lots of every ISIN
+    /// closed in the year, weighted by cost and sorted by ISIN.
+    /// Lots without an annualized return are left out
+    pub fn annualized_return_by_product(&self) -> Vec<(String, d128)> {
+        // cost weighted returns and the costs per ISIN
+        let mut returns: BTreeMap<&str, (d128, d128)> = BTreeMap::new();
+        for lot in self
+            .closed_lots
+            .iter()
+            .filter(|l| l.sold.year() == self.year)
+        {
+            if let Some(rate) = lot.annualized_return() {
+                let sum = returns.entry(&lot.isin).or_default();
+                sum.0 += rate * lot.cost.amount;
+                sum.1 += lot.cost.amount;
             }
+        }
+        returns
+            .into_iter()
+            .map(|(isin, (weighted, cost))| (isin.to_string(), weighted / cost))
+            .collect()
+    }
 
-            if let Some(next) = tr_pin.as_mut().peek().await {
-                match next {
-                    Ok(nt) => {
-                        if nt.date.year() > year {
-                            break;
-                        }
-                    }
-                    Err(e) => return Err(anyhow!("{}", e)),
+    /// returns the realized profit of every ISIN in the year
+    /// as a percentage of the cost of the sold shares, sorted by ISIN.
+    /// ISINs sold without a cost are left out
+    pub fn return_pct_by_product(&self) -> Vec<(String, d128)> {
+        let costs = self.product_costs.get(&self.year);
+        self.profit_by_product()
+            .into_iter()
+            .filter_map(|(isin, profit)| {
+                let cost = costs?.get(&isin)?.amount;
+                if cost.is_zero() {
+                    return None;
                 }
+                Some((isin, profit.amount / cost * d128::from(100)))
+            })
+            .collect()
+    }
+
+    /// like `profit_by_product`, but returns the products with a profit or loss
+    /// smaller than `threshold` summed up separately as the second element
+    pub fn profit_by_product_above(
+        &self,
+        threshold: &Money,
+    ) -> Result<(Vec<(String, Money)>, Money), Error> {
+        let mut other = Money::default();
+        let mut products = Vec::new();
+
+        for (isin, profit) in self.profit_by_product() {
+            if profit.amount.abs() < threshold.amount.abs() {
+                other.add(&profit)?;
+            } else {
+                products.push((isin, profit));
             }
         }
+        Ok((products, other))
+    }
 
-        Ok(Report {
-            profits,
-            years_carry_losses: self.years_carry_losses,
-            year,
-        })
+    /// returns the realized profit of every category in the year, sorted by category.
+    /// ISINs without one, see `Portfolio::categories`, are under "other"
+    pub fn profit_by_category(&self) -> Result<Vec<(String, Money)>, Error> {
+        let mut categories: BTreeMap<&str, Money> = BTreeMap::new();
+        for (isin, profit) in self.profit_by_product() {
+            let category = self.categories.get(&isin).map_or("other", String::as_str);
+            categories.entry(category).or_default().add(&profit)?;
+        }
+        Ok(categories
+            .into_iter()
+            .map(|(category, profit)| (category.to_string(), profit))
+            .collect())
     }
-}
 
-#[derive(Debug, Default)]
-struct State {
-    total: Money,
-    avg: Money,
-    qty: isize,
-}
+    /// returns the largest gain and the largest loss of a single sell in `year`,
+    /// with its ISIN and date. None without gains or losses
+    pub fn extremes(&self, year: i32) -> (Option<Sale>, Option<Sale>) {
+        let sells = self.sells.get(&year).map(Vec::as_slice).unwrap_or_default();
+        let best = sells
+            .iter()
+            .filter(|(_, _, profit)| profit.amount.is_positive())
+            .max_by(|a, b| {
+                a.2.amount
+                    .partial_cmp(&b.2.amount)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+        let worst = sells
+            .iter()
+            .filter(|(_, _, profit)| profit.is_negative())
+            .min_by(|a, b| {
+                a.2.amount
+                    .partial_cmp(&b.2.amount)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+        (best.cloned(), worst.cloned())
+    }
 
-#[cfg(test)]
-mod test {
-    use crate::portfolio::Portfolio;
-    use crate::{Money, Transaction};
-    use chrono::NaiveDate;
-    use decimal::d128;
-    use futures::stream;
+    /// returns the transaction costs paid in `year`,
+    /// regardless of how they affect the profit
+    pub fn total_fees(&self, year: i32) -> Money {
+        self.fees.get(&year).cloned().unwrap_or_default()
+    }
 
-    #[tokio::test]
-    async fn losses_carry_over() {
-        let from = NaiveDate::from_ymd(2020, 1, 1);
-        let to = NaiveDate::from_ymd(2021, 1, 1);
+    /// the cash received minus the cash paid in `year`: the signed values
+    /// of all transactions net of their fees, regardless of the cost basis.
+    /// Fails when they are in different currencies, see `Portfolio::base_currency`
+    pub fn net_cash_flow(&self, year: i32) -> Result<Money, Error> {
+        let mut net = Money::default();
+        for (_, flow) in self.cash_flows.iter().filter(|((y, _), _)| *y == year) {
+            net.add(flow)?;
+        }
+        Ok(net)
+    }
 
-        let transactions = vec![
-            Ok(Transaction::new_unchecked(
-                from.clone(),
-                "1".to_string(),
-                1,
-                Money::new(d128::from(-500_i32)),
-                "id".to_string(),
-            )),
-            Ok(Transaction::new_unchecked(
-                to.clone(),
-                "1".to_string(),
-                -1,
-                Money::new(d128::from(400_i32)),
-                "id".to_string(),
-            )),
-        ];
+    /// the traded volume of `year`: the summed up absolute values of the buys and sells.
+    /// Fails when they are in different currencies, see `Portfolio::base_currency`
+    pub fn turnover(&self, year: i32) -> Result<Money, Error> {
+        let mut total = Money::default();
+        for (_, traded) in self.turnover.iter().filter(|((y, _), _)| *y == year) {
+            total.add(traded)?;
+        }
+        Ok(total)
+    }
 
-        let portfolio = Portfolio::with_carry_losses(stream::iter(transactions), 1);
-        let report = portfolio.report(2021).await.unwrap();
+    /// the hash of the input set with `Portfolio::source_hash`
+    pub fn source_hash(&self) -> Option<&str> {
+        self.source_hash.as_deref()
+    }
 
-        assert_eq!(
-            report.adjusted_profit().unwrap(),
-            Money::new(d128::from(-100))
-        )
+    /// every accounted buy and sell in order, recorded with `Portfolio::audit`
+    pub fn audit(&self) -> &[AuditEntry] {
+        &self.audit
     }
 
-    #[tokio::test]
-    async fn losses_carry_over_different_isin() {
-        let from = NaiveDate::from_ymd(2020, 1, 1);
-        let to = NaiveDate::from_ymd(2021, 1, 1);
+    pub fn processed(&self) -> Processed {
+        self.processed
+    }
 
-        let transactions = vec![
-            Ok(Transaction::new_unchecked(
-                from.clone(),
-                "2".to_string(),
-                1,
-                Money::new(d128::from(-500_i32)),
-                "id".to_string(),
-            )),
-            Ok(Transaction::new_unchecked(
-                from.clone(),
-                "1".to_string(),
-                1,
-                Money::new(d128::from(-500_i32)),
-                "id".to_string(),
-            )),
-            Ok(Transaction::new_unchecked(
-                to.clone(),
-                "1".to_string(),
-                -1,
-                Money::new(d128::from(400_i32)),
-                "id".to_string(),
-            )),
-            Ok(Transaction::new_unchecked(
-                to.clone(),
-                "2".to_string(),
-                -1,
-                Money::new(d128::from(500_i32)),
-                "id".to_string(),
-            )),
-        ];
+    /// returns the sorted years with realized profits
+    pub fn years(&self) -> Vec<i32> {
+        let mut years: Vec<i32> = self.profits.keys().copied().collect();
+        years.sort_unstable();
+        years
+    }
 
-        let portfolio = Portfolio::with_carry_losses(stream::iter(transactions), 1);
-        let report = portfolio.report(2021).await.unwrap();
+    /// returns the accrued interest received minus paid in `year`,
+    /// which is income rather than part of the capital gains
+    pub fn accrued_interest(&self, year: i32) -> Money {
+        self.accrued_interest
+            .get(&year)
+            .cloned()
+            .unwrap_or_default()
+    }
 
-        assert_eq!(
-            report.adjusted_profit().unwrap(),
-            Money::new(d128::from(-100))
-        )
+    /// returns the sorted currencies of the transactions, before any conversion
+    pub fn currencies(&self) -> Vec<String> {
+        self.currencies.iter().cloned().collect()
     }
 
-    #[tokio::test]
-    async fn losses_carry_over_different_isin_multiple_years() {
-        let from = NaiveDate::from_ymd(2020, 1, 1);
-        let to = NaiveDate::from_ymd(2021, 1, 1);
+    /// whether transactions came in more than one currency
+    /// without a base currency to convert them to
+    pub fn mixed_currencies(&self) -> bool {
+        self.warnings
+            .iter()
+            .any(|w| matches!(w, Warning::MixedCurrencies(_)))
+    }
 
-        let transactions = vec![
-            Ok(Transaction::new_unchecked(
-                from.clone(),
-                "2".to_string(),
-                1,
-                Money::new(d128::from(-500_i32)),
-                "id".to_string(),
-            )),
-            Ok(Transaction::new_unchecked(
-                from.clone(),
-                "1".to_string(),
-                2,
-                Money::new(d128::from(-1000_i32)),
-                "id".to_string(),
-            )),
-            Ok(Transaction::new_unchecked(
-                from.clone(),
-                "1".to_string(),
-                -1,
-                Money::new(d128::from(400_i32)),
-                "id".to_string(),
-            )),
-            Ok(Transaction::new_unchecked(
-                to.clone(),
-                "1".to_string(),
-                -1,
-                Money::new(d128::from(400_i32)),
-                "id".to_string(),
-            )),
-            Ok(Transaction::new_unchecked(
-                to.clone(),
-                "2".to_string(),
-                -1,
-                Money::new(d128::from(400_i32)),
-                "id".to_string(),
-            )),
-        ];
+    // accounts the profit of `tr` closing a position opened for `proceeds`,
+    // warning about a loss above `loss_limit`
+    fn realize(
+        &mut self,
+        tr: &Transaction,
+        proceeds: &Money,
+        profit: &Money,
+        loss_limit: Option<&Money>,
+    ) -> Result<(), Error> {
+        if let Some(limit) = loss_limit {
+            let mut excess = profit.clone();
+            excess.add(limit)?;
+            if excess.is_negative() {
+                self.warnings.push(Warning::LargeLoss {
+                    isin: tr.isin.clone(),
+                    date: tr.date,
+                    loss: profit.clone(),
+                });
+            }
+        }
+        let year = self.profits.entry(tr.date.year()).or_default();
+        if profit.is_negative() {
+            year.1.add(profit)?;
+        } else {
+            year.0.add(profit)?;
+        }
 
-        let portfolio = Portfolio::with_carry_losses(stream::iter(transactions), 2);
-        let report = portfolio.report(2021).await.unwrap();
+        self.sells.entry(tr.date.year()).or_default().push((
+            tr.isin.clone(),
+            tr.date,
+            profit.clone(),
+        ));
+        self.product_profits
+            .entry(tr.date.year())
+            .or_default()
+            .entry(tr.isin.clone())
+            .or_default()
+            .add(profit)?;
+        let mut basis = proceeds.clone();
+        basis.sub(profit)?;
+        self.product_costs
+            .entry(tr.date.year())
+            .or_default()
+            .entry(tr.isin.clone())
+            .or_default()
+            .add(&basis)
+    }
 
-        assert_eq!(
-            report.adjusted_profit().unwrap(),
-            Money::new(d128::from(-300))
-        )
+    // fails in strict mode, otherwise records the skipped transaction
+    fn skip(&mut self, strictness: Strictness, err: anyhow::Error) -> anyhow::Result<()> {
+        let reason = err.to_string();
+        strictness.check(err)?;
+        self.processed.skipped += 1;
+        self.warnings.push(Warning::Skipped(reason));
+        Ok(())
+    }
+
+    /// returns the gains and losses of every year, sorted by year
+    pub fn profit_map(&self) -> BTreeMap<i32, YearProfit> {
+        self.profits
+            .iter()
+            .map(|(year, (gains, losses))| {
+                let profit = YearProfit {
+                    gains: gains.clone(),
+                    losses: losses.clone(),
+                };
+                (*year, profit)
+            })
+            .collect()
+    }
+
+    // removes everything accumulated for `year`
+    fn take_year(&mut self, year: i32) -> YearProfit {
+        self.product_profits.remove(&year);
+        self.product_costs.remove(&year);
+        self.local_profits.remove(&year);
+        self.exchange_rates.retain(|(y, _), _| *y != year);
+        self.fees.remove(&year);
+        self.cash_flows.retain(|(y, _), _| *y != year);
+        self.turnover.retain(|(y, _), _| *y != year);
+        self.sells.remove(&year);
+        self.accrued_interest.remove(&year);
+        self.closed_lots.retain(|lot| lot.sold.year() != year);
+        let (gains, losses) = self.profits.remove(&year).unwrap_or_default();
+        YearProfit { gains, losses }
+    }
+
+    /// returns the gains, losses and their net of every year as TOML,
+    /// under a `years` table keyed by year, with amounts as strings
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        #[derive(Serialize)]
+        struct Year {
+            #[serde(flatten)]
+            profit: YearProfit,
+            net: Money,
+        }
+
+        let mut years = BTreeMap::new();
+        for (year, profit) in self.profit_map() {
+            let mut net = profit.gains.clone();
+            net.add(&profit.losses)?;
+            years.insert(year.to_string(), Year { profit, net });
+        }
+        let mut doc = BTreeMap::new();
+        doc.insert("years", years);
+        Ok(toml::to_string(&doc)?)
+    }
+
+    /// returns the profits of the year and every warning as one JSON document,
+    /// `{ "report": .., "warnings": [..] }`, with amounts as strings
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        #[derive(Serialize)]
+        struct Summary {
+            year: i32,
+            profit: Money,
+            adjusted_profit: Money,
+            taxable_profit: Money,
+            years: BTreeMap<i32, YearProfit>,
+        }
+        #[derive(Serialize)]
+        struct Envelope<'a> {
+            report: Summary,
+            warnings: &'a [Warning],
+        }
+
+        let report = Summary {
+            year: self.year,
+            profit: self.profit()?,
+            adjusted_profit: self.adjusted_profit()?,
+            taxable_profit: self.taxable_profit()?,
+            years: self.profit_map(),
+        };
+        Ok(serde_json::to_string(&Envelope {
+            report,
+            warnings: &self.warnings,
+        })?)
+    }
+
+    /// returns the summed up profitable sells of `year`
+    pub fn gains(&self, year: i32) -> Money {
+        self.profits
+            .get(&year)
+            .map(|p| p.0.clone())
+            .unwrap_or_default()
+    }
+
+    /// returns the summed up losing sells of `year`, as a negative amount
+    pub fn losses(&self, year: i32) -> Money {
+        self.profits
+            .get(&year)
+            .map(|p| p.1.clone())
+            .unwrap_or_default()
+    }
+
+    /// returns the gains and losses of `year` as positive amounts,
+    /// for forms with separate lines for both
+    pub fn split_by_sign(&self, year: i32) -> (Money, Money) {
+        (self.gains(year).abs(), self.losses(year).abs())
+    }
+
+    ///returns the total profits,
+    /// zero when nothing was sold in the year
+    pub fn profit(&self) -> anyhow::Result<Money> {
+        let profit = match self.profits.get(&self.year) {
+            Some(profit) => profit,
+            None => return Ok(Money::default()),
+        };
+
+        let mut res = profit.0.clone();
+        res.add(&profit.1)?;
+        Ok(res)
+    }
+
+    /// the year the report is for
+    pub fn year(&self) -> i32 {
+        self.year
+    }
+
+    /// returns a copy of the report for another year with realized profits,
+    /// without replaying the transactions. The open positions and their costs
+    /// stay those at the end of the year of `self`
+    pub fn with_year(&self, year: i32) -> anyhow::Result<Report> {
+        if !self.profits.contains_key(&year) {
+            return Err(anyhow!("no profits in the year: {}", year));
+        }
+        Ok(Report {
+            year,
+            ..self.clone()
+        })
+    }
+
+    /// the tax free gains of the year, if any
+    pub fn annual_exemption(&self) -> Option<&Money> {
+        self.annual_exemption.as_ref()
+    }
+
+    /// returns the cost basis of the shares sold in the year
+    pub fn sold_cost(&self) -> Result<Money, Error> {
+        let mut total = Money::default();
+        for cost in self
+            .product_costs
+            .get(&self.year)
+            .into_iter()
+            .flat_map(|c| c.values())
+        {
+            total.add(cost)?;
+        }
+        Ok(total)
+    }
+
+    /// returns how much of the losses carried over
+    /// offset the gain of the year, as a positive amount
+    pub fn carried_losses_applied(&self) -> Result<Money, Error> {
+        let (net, _) = self.carry_losses()?;
+        let mut applied = self.gains(self.year);
+        applied.add(&self.losses(self.year))?;
+        applied.sub(&net)?;
+        if applied.is_negative() {
+            return Ok(Money::default());
+        }
+        Ok(applied.truncate_trailing_zeros())
+    }
+
+    /// returns `adjusted_profit` minus the annual exemption, but not below zero.
+    /// Without an exemption it is the same as `adjusted_profit`
+    pub fn taxable_profit(&self) -> Result<Money, Error> {
+        let mut profit = self.adjusted_profit()?;
+        if let Some(exemption) = &self.annual_exemption {
+            if profit.is_negative() {
+                return Ok(profit);
+            }
+            profit.sub(exemption)?;
+            if profit.is_negative() {
+                profit = Money {
+                    amount: d128::from(0),
+                    currency: profit.currency,
+                };
+            }
+        }
+        Ok(profit.truncate_trailing_zeros())
+    }
+
+    /// a rough estimate of the tax owed on `taxable_profit`, not tax advice.
+    /// A loss owes nothing
+    pub fn estimate_tax(&self, schedule: &TaxSchedule) -> Result<Money, Error> {
+        let profit = self.taxable_profit()?;
+        let mut tax = d128::from(0);
+
+        for (i, (from, rate)) in schedule.brackets.iter().enumerate() {
+            let to = match schedule.brackets.get(i + 1) {
+                Some((next, _)) => profit.amount.min(*next),
+                None => profit.amount,
+            };
+            if to > *from {
+                tax += (to - *from) * *rate;
+            }
+        }
+
+        Ok(Money {
+            amount: tax,
+            currency: profit.currency,
+        }
+        .truncate_trailing_zeros())
+    }
+
+    /// returns `adjusted_profit` with exactly `decimals` decimals,
+    /// keeping the trailing zeros tax forms expect
+    pub fn adjusted_profit_fixed(&self, decimals: u32) -> Result<Money, Error> {
+        Ok(self.adjusted_profit()?.fixed(decimals))
+    }
+
+    /// returns the profits minus the losses carried over
+    /// from other years under the carry loss policy.
+    /// Unless losses are carried back, gains from previous years offset nothing,
+    /// so when the year itself is a loss, that loss is returned
+    /// together with the losses still carried over,
+    /// as it is to be carried forward to future years
+    pub fn adjusted_profit(&self) -> Result<Money, Error> {
+        let (mut profit, losses) = self.carry_losses()?;
+        for (_, loss) in &losses {
+            profit.add(loss)?;
+        }
+        Ok(profit.truncate_trailing_zeros())
+    }
+
+    /// returns the losses left to be carried forward after the year,
+    /// including a loss of the year itself, as a negative amount
+    pub fn remaining_carry_loss(&self) -> Result<Money, Error> {
+        let (net, losses) = self.carry_losses()?;
+        let mut remaining = Money::default();
+        if net.is_negative() {
+            remaining = net;
+        }
+        for (_, loss) in &losses {
+            remaining.add(loss)?;
+        }
+        Ok(remaining.truncate_trailing_zeros())
+    }
+
+    // offsets the years up to the report year under the carry loss policy,
+    // returns what is left of the report year and of the losses before it
+    fn carry_losses(&self) -> Result<(Money, Vec<(i32, Money)>), Error> {
+        let policy = self.carry_loss_policy;
+        let mut prf: Vec<(i32, Money)> = Vec::new();
+        for (year, prof) in self.profits.iter().filter(|(k, _)| **k <= self.year) {
+            let mut add = prof.0.clone();
+            add.add(&prof.1)?;
+            prf.push((*year, add));
+        }
+        prf.sort_by_key(|a| a.0);
+
+        // what is left of the losses and gains of earlier years, oldest first
+        let mut losses: Vec<(i32, Money)> = Vec::new();
+        let mut gains: Vec<(i32, Money)> = Vec::new();
+
+        for (year, mut net) in prf {
+            if let Some(forward) = policy.forward_years {
+                losses.retain(|(y, _)| year - y <= forward as i32);
+            }
+
+            if net.is_negative() {
+                let back = policy.back_years.unwrap_or(0) as i32;
+                for (_, gain) in gains.iter_mut().filter(|(y, _)| year - y <= back) {
+                    offset(&mut net, gain)?;
+                }
+            } else {
+                for (_, loss) in losses.iter_mut() {
+                    offset(&mut net, loss)?;
+                }
+            }
+
+            if year == self.year {
+                return Ok((net, losses));
+            }
+
+            match net.is_negative() {
+                true => losses.push((year, net)),
+                false => gains.push((year, net)),
+            }
+        }
+
+        Ok((Money::default(), Vec::new()))
+    }
+}
+
+fn merge_products(
+    products: &mut HashMap<String, Money>,
+    other: HashMap<String, Money>,
+) -> Result<(), Error> {
+    for (isin, amount) in other {
+        products.entry(isin).or_default().add(&amount)?;
+    }
+    Ok(())
+}
+
+// cancels out as much as possible of `net` against `other` of the opposite sign
+fn offset(net: &mut Money, other: &mut Money) -> Result<(), Error> {
+    let used = match other.amount.abs() < net.amount.abs() {
+        true => other.clone(),
+        false => net.negate(),
+    };
+    net.add(&used)?;
+    other.sub(&used)
+}
+
+// The Stream is expected to produce transactions in order
+// from oldest to newest
+pub struct Portfolio<S: Stream<Item = anyhow::Result<Transaction>>> {
+    tr_stream: S,
+    carry_loss_policy: CarryLossPolicy,
+    cost_basis: CostBasisMethod,
+    style: ReportStyle,
+    annual_exemption: Option<Money>,
+    source_hash: Option<String>,
+    strictness: Strictness,
+    base_currency: Option<String>,
+    classifier: Box<Classifier>,
+    disallow_shorts: bool,
+    include_cancelled: bool,
+    excluded_orders: HashSet<String>,
+    audit: bool,
+    local_profits: bool,
+    avg_rounding: Option<(u32, Rounding)>,
+    currency_overrides: HashMap<String, String>,
+    since: Option<NaiveDate>,
+    drip: DripTreatment,
+    loss_limit: Option<Money>,
+    check_totals: bool,
+    capitalize_fees: bool,
+    categories: HashMap<String, String>,
+    // receives every year once it is complete, see `stream_years`
+    year_sink: Option<UnboundedSender<(i32, YearProfit)>>,
+    // receives every sell with its profit, see `realized_profits`
+    sell_sink: Option<UnboundedSender<(Transaction, Money)>>,
+}
+
+/// the ISIN, date and realized profit of a sell
+pub type Sale = (String, NaiveDate, Money);
+
+/// decides whether a transaction is a buy, a sell or neither
+pub type Classifier = dyn Fn(&Transaction) -> TransactionType + Send + Sync;
+
+impl<S: Stream<Item = anyhow::Result<Transaction>>> Portfolio<S> {
+    pub fn new(tr_stream: S) -> Self {
+        Self::with_carry_losses(tr_stream, 0)
+    }
+
+    pub fn with_carry_losses(tr_stream: S, years_carry_losses: u8) -> Self {
+        Self {
+            tr_stream,
+            carry_loss_policy: CarryLossPolicy::flat(years_carry_losses),
+            cost_basis: CostBasisMethod::default(),
+            style: ReportStyle::default(),
+            annual_exemption: None,
+            source_hash: None,
+            strictness: Strictness::default(),
+            base_currency: None,
+            classifier: Box::new(Transaction::r#type),
+            disallow_shorts: false,
+            include_cancelled: false,
+            excluded_orders: HashSet::new(),
+            audit: false,
+            local_profits: false,
+            avg_rounding: None,
+            currency_overrides: HashMap::new(),
+            since: None,
+            drip: DripTreatment::default(),
+            loss_limit: None,
+            check_totals: false,
+            capitalize_fees: false,
+            categories: HashMap::new(),
+            year_sink: None,
+            sell_sink: None,
+        }
+    }
+
+    /// replaces the flat carry over window of `with_carry_losses`
+    pub fn carry_loss_policy(mut self, policy: CarryLossPolicy) -> Self {
+        self.carry_loss_policy = policy;
+        self
+    }
+
+    /// sets how the cost of sold shares is determined
+    pub fn cost_basis(mut self, method: CostBasisMethod) -> Self {
+        self.cost_basis = method;
+        self
+    }
+
+    /// sets the conventions of the report, `DegiroPnl` also sets the average cost basis
+    pub fn style(mut self, style: ReportStyle) -> Self {
+        if style == ReportStyle::DegiroPnl {
+            self.cost_basis = CostBasisMethod::Average;
+        }
+        self.style = style;
+        self
+    }
+
+    /// a tax free amount of gains per year, see `Report::taxable_profit`
+    pub fn annual_exemption(mut self, exemption: Money) -> Self {
+        self.annual_exemption = Some(exemption);
+        self
+    }
+
+    /// records the hash of the input on the report, like `CsvStream::source_hash`
+    pub fn source_hash(mut self, hash: &str) -> Self {
+        self.source_hash = Some(hash.to_string());
+        self
+    }
+
+    /// sets how invalid transactions are handled
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// overrides the default classification by the sign of the value
+    pub fn classifier<F>(mut self, classifier: F) -> Self
+    where
+        F: Fn(&Transaction) -> TransactionType + Send + Sync + 'static,
+    {
+        self.classifier = Box::new(classifier);
+        self
+    }
+
+    /// fails on any sell that would leave a negative quantity,
+    /// which on a cash account means buys are missing
+    pub fn disallow_shorts(mut self, disallow_shorts: bool) -> Self {
+        self.disallow_shorts = disallow_shorts;
+        self
+    }
+
+    /// accounts for rows marked as cancelled or pending orders,
+    /// which are excluded by default
+    pub fn include_cancelled(mut self, include_cancelled: bool) -> Self {
+        self.include_cancelled = include_cancelled;
+        self
+    }
+
+    /// drops the transactions of these orders, like erroneous ones
+    /// or ones accounted for elsewhere
+    pub fn exclude_orders<I>(mut self, order_ids: I) -> Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.excluded_orders.extend(order_ids);
+        self
+    }
+
+    /// records the position before and after every buy and sell,
+    /// see `Report::audit`
+    pub fn audit(mut self, audit: bool) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// also accounts for the profits in the local currency of every ISIN,
+    /// by the average cost of the local values.
+    /// See `Report::local_profit_by_product`
+    pub fn local_profits(mut self, local: bool) -> Self {
+        self.local_profits = local;
+        self
+    }
+
+    /// rounds the average cost to `decimals` every time it is recomputed,
+    /// to match the rounded figures of the broker. Unrounded by default
+    pub fn round_avg(mut self, decimals: u32, rounding: Rounding) -> Self {
+        self.avg_rounding = Some((decimals, rounding));
+        self
+    }
+
+    /// forces the currency of the transactions of an ISIN,
+    /// for exports where it is missing or wrong. Maps ISINs to currency codes
+    pub fn currency_overrides(mut self, overrides: HashMap<String, String>) -> Self {
+        self.currency_overrides = overrides;
+        self
+    }
+
+    /// warns about every transaction whose total is not its value plus the fee,
+    /// see `Warning::TotalMismatch`
+    pub fn check_totals(mut self, check: bool) -> Self {
+        self.check_totals = check;
+        self
+    }
+
+    /// adds the fees of buys to their cost basis, raising the average cost,
+    /// for where fees are capitalized. `ReportStyle::DegiroPnl` already does
+    pub fn capitalize_fees(mut self, capitalize: bool) -> Self {
+        self.capitalize_fees = capitalize;
+        self
+    }
+
+    /// groups the ISINs into categories like "ETF" or "Bond",
+    /// see `Report::profit_by_category`. Maps ISINs to categories
+    pub fn categories(mut self, categories: HashMap<String, String>) -> Self {
+        self.categories = categories;
+        self
+    }
+
+    /// only reports the sells from `date` on,
+    /// earlier buys still make up the cost basis
+    pub fn since(mut self, date: NaiveDate) -> Self {
+        self.since = Some(date);
+        self
+    }
+
+    /// sets how dividends paid in shares are accounted for, at zero cost by default
+    pub fn drip(mut self, treatment: DripTreatment) -> Self {
+        self.drip = treatment;
+        self
+    }
+
+    /// warns about every sell that loses more than `limit`, see `Warning::LargeLoss`
+    pub fn warn_loss(mut self, limit: Money) -> Self {
+        self.loss_limit = Some(limit.abs());
+        self
+    }
+
+    /// converts every transaction to `currency` before it is accounted for,
+    /// so positions bought in different currencies share one average price
+    pub fn base_currency(mut self, currency: &str) -> Self {
+        self.base_currency = Some(currency.to_lowercase());
+        self
+    }
+
+    fn calc_trans_profit(tr: &Transaction, entry: &State) -> Result<Money, Error> {
+        let mut avg_price = entry.avg.clone();
+        avg_price.mul(tr.quantity)?;
+
+        let mut local_profit = tr.value.clone();
+        let abs_avg = avg_price.abs();
+        local_profit.sub(&abs_avg)?;
+        Ok(local_profit)
+    }
+
+    pub async fn report(self, year: i32) -> anyhow::Result<Report> {
+        let end = NaiveDate::from_ymd_opt(year, 12, 31)
+            .ok_or_else(|| anyhow!("invalid year: {}", year))?;
+        self.report_until(end).await
+    }
+
+    /// yields the profit of every year as soon as the year is complete
+    /// and frees its accumulators, positions carry over to the next year
+    pub fn stream_years(mut self) -> impl Stream<Item = anyhow::Result<(i32, YearProfit)>> {
+        let (sink, years) = mpsc::unbounded();
+        self.year_sink = Some(sink);
+        self.replay_into(years)
+    }
+
+    /// yields every sell with its realized profit as it is accounted for,
+    /// to group the profits in other ways than per year or product
+    pub fn realized_profits(mut self) -> impl Stream<Item = anyhow::Result<(Transaction, Money)>> {
+        let (sink, sells) = mpsc::unbounded();
+        self.sell_sink = Some(sink);
+        self.replay_into(sells)
+    }
+
+    /// returns the cumulative realized profit at the end of every day with a sell,
+    /// oldest first, to chart. See `render::write_equity_curve`
+    pub async fn equity_curve(self) -> anyhow::Result<Vec<(NaiveDate, Money)>> {
+        let sells = self.realized_profits();
+        pin_mut!(sells);
+        let mut curve: Vec<(NaiveDate, Money)> = Vec::new();
+        let mut cumulative = Money::default();
+        while let Some(sell) = sells.next().await {
+            let (tr, profit) = sell?;
+            cumulative.add(&profit)?;
+            match curve.last_mut() {
+                Some((date, value)) if *date == tr.date => *value = cumulative.clone(),
+                _ => curve.push((tr.date, cumulative.clone())),
+            }
+        }
+        Ok(curve)
+    }
+
+    // replays all transactions while yielding what the replay sends to `items`
+    fn replay_into<T>(self, items: UnboundedReceiver<T>) -> impl Stream<Item = anyhow::Result<T>> {
+        let replay = stream::once(self.replay(NaiveDate::MAX.year(), NaiveDate::MAX))
+            .filter_map(|replay| async move { replay.err().map(Err) });
+        // sent items are taken before the replay goes on
+        stream::select_with_strategy(items.map(Ok), replay, |_: &mut ()| PollNext::Left)
+    }
+
+    /// like `report` for the year of `end`,
+    /// but only with the transactions up to and including `end`
+    pub async fn report_until(self, end: NaiveDate) -> anyhow::Result<Report> {
+        let year = end.year();
+        let mut replay = self.replay(year, end).await?;
+
+        if replay.years.is_none() {
+            replay.report.warnings.push(Warning::NoTransactions);
+        }
+        if let Some((first, last)) = replay.years {
+            if year < first {
+                return Err(anyhow!(
+                    "no data for year: {} transactions start in: {}",
+                    year,
+                    first
+                ));
+            }
+            if year > last {
+                return Err(anyhow!(
+                    "no data for year: {} available years: {}-{}",
+                    year,
+                    first,
+                    last
+                ));
+            }
+        }
+        for (isin, state) in replay.positions.iter().filter(|(_, state)| state.qty != 0) {
+            let mut cost = state.avg.clone();
+            cost.mul(state.qty)?;
+            replay.report.open_costs.insert(isin.clone(), cost);
+        }
+        Ok(replay.report)
+    }
+
+    /// checks that every ISIN starts with a buy and is never oversold
+    /// and that trades have an order id.
+    /// Short positions also open with a sell, so with shorts allowed
+    /// their issues are expected
+    pub async fn validate(self) -> anyhow::Result<Vec<Issue>> {
+        let mut issues = Vec::new();
+        let mut held: HashMap<String, isize> = HashMap::new();
+        let tr_stream = self.tr_stream;
+        pin_mut!(tr_stream);
+
+        while let Some(tr) = tr_stream.next().await {
+            let tr = match tr {
+                Ok(tr) => tr,
+                Err(e) => {
+                    self.strictness.check(e)?;
+                    continue;
+                }
+            };
+
+            let r#type = (self.classifier)(&tr);
+            if r#type != TransactionType::Other && tr.order_id.trim().is_empty() {
+                issues.push(Issue::BlankOrderId {
+                    isin: tr.isin.clone(),
+                    date: tr.date,
+                });
+            }
+
+            match r#type {
+                TransactionType::Buy => *held.entry(tr.isin.clone()).or_default() += tr.quantity,
+                TransactionType::Sell => {
+                    let issue = match held.get_mut(&tr.isin) {
+                        None => Issue::SellBeforeBuy {
+                            isin: tr.isin.clone(),
+                            order_id: tr.order_id.clone(),
+                            date: tr.date,
+                        },
+                        Some(qty) => {
+                            *qty += tr.quantity;
+                            if *qty >= 0 {
+                                continue;
+                            }
+                            Issue::NegativeCostBasis {
+                                isin: tr.isin.clone(),
+                                order_id: tr.order_id.clone(),
+                                date: tr.date,
+                            }
+                        }
+                    };
+                    held.entry(tr.isin.clone()).or_insert(tr.quantity);
+                    issues.push(issue);
+                }
+                TransactionType::Other => {}
+            }
+        }
+        Ok(issues)
+    }
+
+    /// returns how many ISINs have an open position after every buy and sell,
+    /// with the date of the transaction
+    pub async fn open_positions(mut self) -> anyhow::Result<Vec<(NaiveDate, usize)>> {
+        self.audit = true;
+        let replay = self.replay(NaiveDate::MAX.year(), NaiveDate::MAX).await?;
+
+        let mut open: HashSet<String> = HashSet::new();
+        Ok(replay
+            .report
+            .audit
+            .into_iter()
+            .map(|entry| {
+                match entry.after.qty {
+                    0 => open.remove(&entry.isin),
+                    _ => open.insert(entry.isin),
+                };
+                (entry.date, open.len())
+            })
+            .collect())
+    }
+
+    /// returns the quantity and average price of every open position
+    /// after the transactions up to and including `date`
+    pub async fn positions_as_of(
+        self,
+        date: NaiveDate,
+    ) -> anyhow::Result<HashMap<String, (isize, Money)>> {
+        let replay = self.replay(date.year(), date).await?;
+
+        Ok(replay
+            .positions
+            .into_iter()
+            .filter(|(_, state)| state.qty != 0)
+            .map(|(isin, state)| (isin, (state.qty, state.avg)))
+            .collect())
+    }
+
+    // processes the transactions up to and including `end`
+    async fn replay(self, year: i32, end: NaiveDate) -> anyhow::Result<Replay> {
+        let strictness = self.strictness;
+        let avg_rounding = self.avg_rounding;
+        let tr_peek = self.tr_stream.peekable();
+        pin_mut!(tr_peek);
+        let mut tr_pin: Pin<&mut _> = tr_peek;
+
+        let mut state_map: HashMap<String, State> = HashMap::new();
+        let mut report = Report {
+            carry_loss_policy: self.carry_loss_policy,
+            annual_exemption: self.annual_exemption.clone(),
+            source_hash: self.source_hash.clone(),
+            categories: self.categories.clone(),
+            year,
+            ..Default::default()
+        };
+        let mut years: Option<(i32, i32)> = None;
+        let mut present: BTreeSet<i32> = BTreeSet::new();
+
+        'transactions: while let Some(tr) = tr_pin.as_mut().next().await {
+            let tr = match tr {
+                Ok(tr) => tr,
+                Err(e) => {
+                    report.skip(strictness, e)?;
+                    continue;
+                }
+            };
+
+            let tr = match self.currency_overrides.get(&tr.isin) {
+                Some(currency) => tr.override_currency(currency),
+                None => tr,
+            };
+            let (_, last) = years.get_or_insert((tr.date.year(), tr.date.year()));
+            *last = tr.date.year();
+            // only the first transaction and the one after a skipped row are not peeked at,
+            // a report for a year before the data stops here
+            if tr.date > end {
+                break;
+            }
+            present.insert(tr.date.year());
+            if let Some(currency) = &tr.value.currency {
+                report.currencies.insert(currency.clone());
+            }
+            let excluded = (!self.include_cancelled && tr.is_cancelled())
+                || self.excluded_orders.contains(&tr.order_id);
+            if let (Some(rate), Some(currency), false) =
+                (tr.exchange_rate(), &tr.local_value.currency, excluded)
+            {
+                let quantity = d128::from(tr.quantity.abs() as i64);
+                let sum = report
+                    .exchange_rates
+                    .entry((tr.date.year(), currency.clone()))
+                    .or_default();
+                sum.0 += rate * quantity;
+                sum.1 += quantity;
+            }
+            if let (true, false, Some(total)) = (self.check_totals, excluded, tr.total()) {
+                let mut expected = tr.value.clone();
+                if let Some(fee) = &tr.fee {
+                    expected.amount -= fee.amount.abs();
+                }
+                // a cent of rounding is tolerated
+                if (total.amount - expected.amount).abs() >= d128!(0.01) {
+                    report.warnings.push(Warning::TotalMismatch {
+                        isin: tr.isin.clone(),
+                        order_id: tr.order_id.clone(),
+                        total: total.clone(),
+                        expected,
+                    });
+                }
+            }
+            let mut tr = match &self.base_currency {
+                Some(currency) => tr.to_currency(currency)?,
+                None => tr,
+            };
+            if !excluded {
+                let flow = report
+                    .cash_flows
+                    .entry((tr.date.year(), tr.value.currency.clone()))
+                    .or_default();
+                flow.add(&tr.value)?;
+                if let Some(fee) = &tr.fee {
+                    flow.sub(&fee.abs())?;
+                }
+            }
+            if let (Some(fee), false) = (&tr.fee, excluded) {
+                report
+                    .fees
+                    .entry(tr.date.year())
+                    .or_default()
+                    .add(&fee.abs())?;
+            }
+
+            let accounted = report.processed.buys + report.processed.sells;
+            let before = state_map.get(&tr.isin).map(State::position);
+            let mut realized = None;
+
+            if tr.checked_type().is_err() {
+                report.warnings.push(Warning::SignMismatch {
+                    isin: tr.isin.clone(),
+                    order_id: tr.order_id.clone(),
+                });
+            }
+
+            let drip = tr.is_drip();
+            let r#type = match drip {
+                true => TransactionType::Buy,
+                false => (self.classifier)(&tr),
+            };
+            // after classifying, a fee above the value of a sell does not make it a buy
+            let negative = tr.value.is_negative();
+            if let (ReportStyle::DegiroPnl, Some(fee)) = (self.style, &tr.fee) {
+                tr.value.sub(&fee.abs())?;
+            }
+            match r#type {
+                _ if excluded => report.processed.skipped += 1,
+                _ if drip && self.drip == DripTreatment::Exclude => report.processed.skipped += 1,
+                _ if tr.is_accrued_interest() => {
+                    report.processed.skipped += 1;
+                    report
+                        .accrued_interest
+                        .entry(tr.date.year())
+                        .or_default()
+                        .add(&tr.value)?;
+                }
+                // corporate actions can come without value and quantity
+                _ if tr.quantity == 0 && tr.value.amount.is_zero() => report.processed.skipped += 1,
+                TransactionType::Buy => {
+                    report.processed.buys += 1;
+                    let entry = state_map.entry(tr.isin.clone()).or_default();
+                    let mut cost = tr.value.abs();
+                    // `DegiroPnl` has already taken the fee from the value
+                    if let (true, ReportStyle::Tax, Some(fee)) =
+                        (self.capitalize_fees, self.style, &tr.fee)
+                    {
+                        cost.add(&fee.abs())?;
+                    }
+                    let mut local_cost = tr.local_value.abs();
+
+                    // buying into a short covers it first, at its share of the cost,
+                    // realizing the proceeds of the short at its average
+                    let covered = tr.quantity.min(-entry.qty).max(0);
+                    if covered > 0 {
+                        let mut covering = cost.clone();
+                        covering.mul(covered)?;
+                        covering.div(tr.quantity)?;
+                        let mut proceeds = entry.avg.clone();
+                        proceeds.mul(covered)?;
+                        let mut profit = proceeds.clone();
+                        profit.sub(&covering)?;
+                        if self.since.is_none_or(|since| tr.date >= since) {
+                            report.realize(&tr, &proceeds, &profit, self.loss_limit.as_ref())?;
+                        }
+                        realized = Some(profit);
+
+                        if self.local_profits {
+                            let mut local_proceeds = entry.local_total.clone();
+                            local_proceeds.mul(covered)?;
+                            local_proceeds.div(-entry.qty)?;
+                            let mut local_covering = local_cost.clone();
+                            local_covering.mul(covered)?;
+                            local_covering.div(tr.quantity)?;
+                            entry.local_total.sub(&local_proceeds)?;
+                            local_cost.sub(&local_covering)?;
+                            let mut profit = local_proceeds;
+                            profit.sub(&local_covering)?;
+                            report
+                                .local_profits
+                                .entry(tr.date.year())
+                                .or_default()
+                                .entry(tr.isin.clone())
+                                .or_default()
+                                .add(&profit)?;
+                        }
+
+                        entry.qty += covered;
+                        cost.sub(&covering)?;
+                        // the rest of the short keeps its average
+                        entry.total = entry.avg.clone();
+                        entry.total.mul(-entry.qty)?;
+                    }
+                    let bought = tr.quantity - covered;
+                    entry.total.add(&cost)?;
+                    if self.local_profits {
+                        entry.local_total.add(&local_cost)?;
+                    }
+                    entry.qty += bought;
+                    if bought > 0 {
+                        let mut price = cost;
+                        price.div(bought)?;
+                        entry.lots.push_back(Lot {
+                            date: tr.date,
+                            order_id: tr.order_id.clone(),
+                            qty: bought,
+                            price,
+                        });
+                    }
+
+                    match entry.qty {
+                        // a covered short leaves nothing to average over,
+                        // the position reopens from a clean average
+                        0 => {
+                            entry.total = Money::default();
+                            entry.avg = Money::default();
+                            entry.local_total = Money::default();
+                        }
+                        qty if qty > 0 => {
+                            let mut avg_price = entry.total.clone();
+                            avg_price.div(qty)?;
+
+                            entry.avg = round_avg(avg_price, avg_rounding);
+                        }
+                        // a short keeps the average it was opened at
+                        _ => {}
+                    }
+                }
+                TransactionType::Sell if tr.quantity == 0 => {
+                    report.skip(
+                        strictness,
+                        anyhow!(
+                            "sell without quantity isin: {} order: {}",
+                            tr.isin,
+                            tr.order_id
+                        ),
+                    )?;
+                }
+                TransactionType::Sell
+                    if self.disallow_shorts
+                        && state_map.get(&tr.isin).map_or(0, |e| e.qty) + tr.quantity < 0 =>
+                {
+                    return Err(anyhow!(
+                        "short position isin: {} order: {} held: {} sold: {}",
+                        tr.isin,
+                        tr.order_id,
+                        state_map.get(&tr.isin).map_or(0, |e| e.qty),
+                        -tr.quantity
+                    ));
+                }
+                // the profit could not be computed, a base currency converts both
+                TransactionType::Sell
+                    if state_map
+                        .get(&tr.isin)
+                        .is_some_and(|entry| entry.conflicts(&tr.value)) =>
+                {
+                    report.skip(
+                        strictness,
+                        anyhow!(
+                            "sell currency {} differs from the position currency {} isin: {} order: {}",
+                            tr.value.currency().unwrap_or("none"),
+                            state_map[&tr.isin].avg.currency().unwrap_or("none"),
+                            tr.isin,
+                            tr.order_id
+                        ),
+                    )?;
+                }
+                // only a custom classifier can get here, like for a corrected sell
+                TransactionType::Sell if negative => {
+                    report.skip(
+                        strictness,
+                        anyhow!(
+                            "sell with a negative value isin: {} order: {}",
+                            tr.isin,
+                            tr.order_id
+                        ),
+                    )?;
+                }
+                TransactionType::Sell => match state_map.get_mut(&tr.isin) {
+                    Some(entry) => {
+                        report.processed.sells += 1;
+                        let held = entry.qty.max(0);
+                        // the matched part stays accounted for in lenient mode
+                        if -tr.quantity > held {
+                            strictness.check(anyhow!(
+                                "sold more than held isin: {} order: {} held: {} sold: {}",
+                                tr.isin,
+                                tr.order_id,
+                                held,
+                                -tr.quantity
+                            ))?;
+                            report.warnings.push(Warning::UnmatchedSell(UnmatchedSell {
+                                isin: tr.isin.clone(),
+                                order_id: tr.order_id.clone(),
+                                date: tr.date,
+                                missing: -tr.quantity - held,
+                            }));
+                        }
+
+                        let preferred = match &self.cost_basis {
+                            CostBasisMethod::Specific(lots) => lots
+                                .get(&tr.order_id)
+                                .map(Vec::as_slice)
+                                .unwrap_or_default(),
+                            _ => &[],
+                        };
+                        let closed = entry.close_lots(-tr.quantity, preferred);
+                        // selling more than is held closes the position
+                        // and opens a short with the rest, at its share of the value
+                        let short = held > 0 && -tr.quantity > held;
+                        let closing = match short {
+                            true => {
+                                let mut closing = tr.clone();
+                                closing.quantity = -held;
+                                for value in [&mut closing.value, &mut closing.local_value] {
+                                    value.mul(held)?;
+                                    value.div(-tr.quantity)?;
+                                }
+                                Cow::Owned(closing)
+                            }
+                            false => Cow::Borrowed(&tr),
+                        };
+                        let mut cost = Money::default();
+                        for lot in &closed {
+                            let mut lot_cost = lot.price.clone();
+                            lot_cost.mul(lot.qty)?;
+                            cost.add(&lot_cost)?;
+                        }
+
+                        let local_profit = match self.cost_basis {
+                            CostBasisMethod::Average => Self::calc_trans_profit(&closing, entry)?,
+                            _ => {
+                                let mut local_profit = closing.value.clone();
+                                local_profit.sub(&cost)?;
+                                local_profit
+                            }
+                        };
+                        if self.since.is_none_or(|since| tr.date >= since) {
+                            report.realize(
+                                &tr,
+                                &closing.value,
+                                &local_profit,
+                                self.loss_limit.as_ref(),
+                            )?;
+                        }
+                        realized = Some(local_profit);
+
+                        if self.local_profits && held > 0 {
+                            let mut cost = entry.local_total.clone();
+                            cost.mul(held.min(-tr.quantity))?;
+                            cost.div(held)?;
+                            entry.local_total.sub(&cost)?;
+                            let mut profit = closing.local_value.clone();
+                            profit.sub(&cost)?;
+                            report
+                                .local_profits
+                                .entry(tr.date.year())
+                                .or_default()
+                                .entry(tr.isin.clone())
+                                .or_default()
+                                .add(&profit)?;
+                        }
+
+                        entry.qty += tr.quantity;
+                        match self.cost_basis {
+                            // the rest keeps its average, recomputing the total
+                            // instead of subtracting keeps the partial sells from drifting
+                            CostBasisMethod::Average if held > 0 => {
+                                entry.total = entry.avg.clone();
+                                entry.total.mul(entry.qty.max(0))?;
+                            }
+                            CostBasisMethod::Average => entry.total.sub(&tr.value)?,
+                            // the average of what is left
+                            _ => {
+                                entry.total.sub(&cost)?;
+                                if entry.qty != 0 {
+                                    let mut avg_price = entry.total.clone();
+                                    avg_price.div(entry.qty)?;
+                                    entry.avg = round_avg(avg_price, avg_rounding);
+                                }
+                            }
+                        }
+                        if short {
+                            let mut proceeds = tr.value.clone();
+                            proceeds.sub(&closing.value)?;
+                            let mut avg_price = proceeds.clone();
+                            avg_price.div(-entry.qty)?;
+                            entry.total = proceeds;
+                            entry.avg = round_avg(avg_price, avg_rounding);
+                            if self.local_profits {
+                                entry.local_total = tr.local_value.clone();
+                                entry.local_total.sub(&closing.local_value)?;
+                            }
+                        }
+
+                        let holding = report.holding_days.entry(tr.isin.clone()).or_default();
+                        for lot in &closed {
+                            holding.0 += (tr.date - lot.date).num_days() * lot.qty as i64;
+                            holding.1 += lot.qty as i64;
+                        }
+                        for lot in closed {
+                            let mut cost = lot.price;
+                            cost.mul(lot.qty)?;
+                            let mut proceeds = tr.value.clone();
+                            proceeds.mul(lot.qty)?;
+                            proceeds.div(-tr.quantity)?;
+                            report.closed_lots.push(ClosedLot {
+                                isin: tr.isin.clone(),
+                                bought: lot.date,
+                                sold: tr.date,
+                                qty: lot.qty,
+                                cost,
+                                proceeds,
+                            });
+                        }
+
+                        // a reopened position starts from a clean average
+                        if entry.qty == 0 {
+                            entry.total = Money::default();
+                            entry.avg = Money::default();
+                            entry.local_total = Money::default();
+                        }
+                    }
+                    // bought before the period the file covers
+                    None => {
+                        report.warnings.push(Warning::UnmatchedSell(UnmatchedSell {
+                            isin: tr.isin.clone(),
+                            order_id: tr.order_id.clone(),
+                            date: tr.date,
+                            missing: -tr.quantity,
+                        }));
+                        report.skip(
+                            strictness,
+                            anyhow!(
+                                "sell without a position isin: {} order: {}",
+                                tr.isin,
+                                tr.order_id
+                            ),
+                        )?;
+                    }
+                },
+                TransactionType::Other => report.processed.skipped += 1,
+            }
+
+            if let (Some(sink), Some(profit)) = (&self.sell_sink, &realized) {
+                if sink.unbounded_send((tr.clone(), profit.clone())).is_ok() {
+                    yield_once().await;
+                }
+            }
+            let traded = report.processed.buys + report.processed.sells > accounted;
+            if traded {
+                report
+                    .turnover
+                    .entry((tr.date.year(), tr.value.currency.clone()))
+                    .or_default()
+                    .add(&tr.value.abs())?;
+            }
+            if self.audit && traded {
+                report.audit.push(AuditEntry {
+                    isin: tr.isin.clone(),
+                    order_id: tr.order_id.clone(),
+                    date: tr.date,
+                    r#type,
+                    before: before.unwrap_or_default(),
+                    after: state_map[&tr.isin].position(),
+                    profit: realized,
+                });
+            }
+
+            // a single peek decides on both the order and the end of the year,
+            // errors are left for the next iteration
+            // and out of order transactions are dropped in lenient mode
+            loop {
+                match tr_pin.as_mut().peek().await {
+                    Some(Ok(nt)) if nt.date < tr.date => {
+                        report.skip(
+                            strictness,
+                            anyhow!(
+                                "transactions are not in order, sort them first tr: {:#?} next: {:#?}",
+                                tr,
+                                nt
+                            ),
+                        )?;
+                        tr_pin.as_mut().next().await;
+                    }
+                    Some(Ok(nt)) if nt.date > end => {
+                        years = years.map(|(first, _)| (first, nt.date.year()));
+                        break 'transactions;
+                    }
+                    Some(Ok(nt)) if nt.date.year() > tr.date.year() => {
+                        send_year(&self.year_sink, &mut report, tr.date.year()).await;
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+        }
+
+        if let Some((_, last)) = years {
+            send_year(&self.year_sink, &mut report, last).await;
+        }
+
+        if let (Some(first), Some(last)) = (present.iter().next(), present.iter().next_back()) {
+            let missing: Vec<i32> = (*first..*last).filter(|y| !present.contains(y)).collect();
+            if !missing.is_empty() {
+                report.warnings.push(Warning::MissingYears(missing));
+            }
+        }
+
+        if report.currencies.len() > 1 && self.base_currency.is_none() {
+            let currencies = report.currencies();
+            warn!("transactions in multiple currencies: {:?}", currencies);
+            report.warnings.push(Warning::MixedCurrencies(currencies));
+        }
+
+        Ok(Replay {
+            report,
+            positions: state_map,
+            years,
+        })
+    }
+}
+
+fn round_avg(avg: Money, rounding: Option<(u32, Rounding)>) -> Money {
+    match rounding {
+        Some((decimals, rounding)) => avg.round(decimals, rounding),
+        None => avg,
+    }
+}
+
+// hands the year over to `stream_years`
+// and waits for it to be taken before the next one starts
+async fn send_year(
+    sink: &Option<UnboundedSender<(i32, YearProfit)>>,
+    report: &mut Report,
+    year: i32,
+) {
+    let sink = match sink {
+        Some(sink) => sink,
+        None => return,
+    };
+    let profit = report.take_year(year);
+    if sink.unbounded_send((year, profit)).is_ok() {
+        yield_once().await;
+    }
+}
+
+// lets the consumer of `replay_into` take what was sent
+async fn yield_once() {
+    let mut sent = false;
+    future::poll_fn(|cx| match sent {
+        true => Poll::Ready(()),
+        false => {
+            sent = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await;
+}
+
+struct Replay {
+    report: Report,
+    positions: HashMap<String, State>,
+    // first and last year of the transactions
+    years: Option<(i32, i32)>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    total: Money,
+    avg: Money,
+    qty: isize,
+    // the average cost in the local currency, see `Portfolio::local_profits`
+    local_total: Money,
+    // the open buys, oldest first
+    lots: VecDeque<Lot>,
+}
+
+impl State {
+    // whether `value` is in another currency than the cost of the position
+    fn conflicts(&self, value: &Money) -> bool {
+        !self.avg.amount.is_zero()
+            && !value.amount.is_zero()
+            && self.avg.currency() != value.currency()
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            qty: self.qty,
+            total: self.total.clone(),
+            avg: self.avg.clone(),
+        }
+    }
+
+    // closes `sold` shares of the lots of the `preferred` buy orders first
+    // and then of the oldest lots, returning the closed parts
+    fn close_lots(&mut self, mut sold: isize, preferred: &[String]) -> Vec<Lot> {
+        let mut order: Vec<usize> = Vec::new();
+        for id in preferred {
+            order.extend((0..self.lots.len()).filter(|i| &self.lots[*i].order_id == id));
+        }
+        order.extend(0..self.lots.len());
+
+        let mut closed = Vec::new();
+        for i in order {
+            let lot = &mut self.lots[i];
+            let qty = sold.min(lot.qty);
+            if qty <= 0 {
+                continue;
+            }
+            closed.push(Lot { qty, ..lot.clone() });
+            lot.qty -= qty;
+            sold -= qty;
+        }
+        self.lots.retain(|lot| lot.qty != 0);
+        closed
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Lot {
+    date: NaiveDate,
+    order_id: String,
+    qty: isize,
+    // per share
+    price: Money,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::Rounding;
+    use crate::portfolio::{
+        AuditEntry, CarryLossPolicy, ClosedLot, CostBasisMethod, DripTreatment, Issue, Portfolio,
+        Position, Processed, Report, ReportStyle, TaxSchedule, UnmatchedSell, Warning, YearProfit,
+    };
+    use crate::test::trade;
+    use crate::{Money, Strictness, Transaction, TransactionType};
+    use chrono::NaiveDate;
+    use decimal::d128;
+    use futures::{stream, StreamExt};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn losses_carry_over() {
+        let from = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let to = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                from,
+                "1".to_string(),
+                1,
+                Money::new(d128::from(-500_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                to,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(400_i32)),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::with_carry_losses(stream::iter(transactions), 1);
+        let report = portfolio.report(2021).await.unwrap();
+
+        assert_eq!(
+            report.adjusted_profit().unwrap(),
+            Money::new(d128::from(-100))
+        )
+    }
+
+    #[tokio::test]
+    async fn losses_carry_over_different_isin() {
+        let from = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let to = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                from,
+                "2".to_string(),
+                1,
+                Money::new(d128::from(-500_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                from,
+                "1".to_string(),
+                1,
+                Money::new(d128::from(-500_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                to,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(400_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                to,
+                "2".to_string(),
+                -1,
+                Money::new(d128::from(500_i32)),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::with_carry_losses(stream::iter(transactions), 1);
+        let report = portfolio.report(2021).await.unwrap();
+
+        assert_eq!(
+            report.adjusted_profit().unwrap(),
+            Money::new(d128::from(-100))
+        )
+    }
+
+    #[tokio::test]
+    async fn losses_carry_over_different_isin_multiple_years() {
+        let from = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let to = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                from,
+                "2".to_string(),
+                1,
+                Money::new(d128::from(-500_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                from,
+                "1".to_string(),
+                2,
+                Money::new(d128::from(-1000_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                from,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(400_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                to,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(400_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                to,
+                "2".to_string(),
+                -1,
+                Money::new(d128::from(400_i32)),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::with_carry_losses(stream::iter(transactions), 2);
+        let report = portfolio.report(2021).await.unwrap();
+
+        assert_eq!(
+            report.adjusted_profit().unwrap(),
+            Money::new(d128::from(-300))
+        )
+    }
+
+    fn out_of_order() -> Vec<anyhow::Result<Transaction>> {
+        vec![
+            Ok(Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2020, 6, 1).unwrap(),
+                "1".to_string(),
+                1,
+                Money::new(d128::from(-500_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(),
+                "1".to_string(),
+                1,
+                Money::new(d128::from(-300_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(),
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(400_i32)),
+                "id".to_string(),
+            )),
+        ]
+    }
+
+    fn oversold() -> Vec<anyhow::Result<Transaction>> {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        vec![Ok(trade(date, "1", 1, -100)), Ok(trade(date, "1", -2, 300))]
+    }
+
+    #[tokio::test]
+    async fn strict_errors_on_out_of_order() {
+        let portfolio = Portfolio::new(stream::iter(out_of_order())).strictness(Strictness::Strict);
+
+        assert!(portfolio.report(2021).await.is_err());
+
+        let portfolio = Portfolio::new(stream::iter(oversold())).strictness(Strictness::Strict);
+        assert_eq!(
+            portfolio.report(2021).await.unwrap_err().to_string(),
+            "sold more than held isin: 1 order: id held: 1 sold: 2"
+        );
+    }
+
+    #[tokio::test]
+    async fn lenient_skips_out_of_order() {
+        let portfolio =
+            Portfolio::new(stream::iter(out_of_order())).strictness(Strictness::Lenient);
+        let report = portfolio.report(2021).await.unwrap();
+
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(-100)));
+        assert!(matches!(report.warnings(), [Warning::Skipped(_)]));
+
+        let portfolio = Portfolio::new(stream::iter(oversold())).strictness(Strictness::Lenient);
+        let report = portfolio.report(2021).await.unwrap();
+
+        // the share held is realized and the other one opens a short
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(50)));
+        assert!(matches!(
+            report.warnings(),
+            [Warning::UnmatchedSell(UnmatchedSell { missing: 1, .. })]
+        ));
+    }
+
+    #[tokio::test]
+    async fn base_currency_average() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::with_currency(d128!(-118.25), "USD".to_string()),
+                "id".to_string(),
+            )
+            .with_exchange_rate(d128!(1.1825))),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::with_currency(d128!(-200), "EUR".to_string()),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -2,
+                Money::with_currency(d128!(400), "EUR".to_string()),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::new(stream::iter(transactions)).base_currency("EUR");
+        let report = portfolio.report(2021).await.unwrap();
+
+        assert_eq!(
+            report.profit().unwrap(),
+            Money::with_currency(d128!(100), "EUR".to_string())
+        )
+    }
+
+    #[tokio::test]
+    async fn custom_classifier() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::new(d128::from(-500_i32)),
+                "id".to_string(),
+            )),
+            // securities lending income, which would be a sell by default
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(5_i32)),
+                "lending".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(600_i32)),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::new(stream::iter(transactions)).classifier(|tr| {
+            match tr.order_id.as_str() {
+                "lending" => TransactionType::Other,
+                _ => tr.r#type(),
+            }
+        });
+        let report = portfolio.report(2021).await.unwrap();
+
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(100)))
+    }
+
+    #[tokio::test]
+    async fn classified_sell_with_negative_value() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let transactions = || {
+            stream::iter(vec![
+                Ok(Transaction::new_unchecked(
+                    date,
+                    "1".to_string(),
+                    2,
+                    Money::new(d128::from(-500_i32)),
+                    "id".to_string(),
+                )),
+                // a correction the default would treat as a buy
+                Ok(Transaction::new_unchecked(
+                    date,
+                    "1".to_string(),
+                    1,
+                    Money::new(d128::from(-10_i32)),
+                    "correction".to_string(),
+                )),
+                Ok(Transaction::new_unchecked(
+                    date,
+                    "1".to_string(),
+                    -1,
+                    Money::new(d128::from(300_i32)),
+                    "id".to_string(),
+                )),
+            ])
+        };
+        let classifier = |tr: &Transaction| match tr.order_id.as_str() {
+            "correction" => TransactionType::Sell,
+            _ => tr.r#type(),
+        };
+
+        let strict = Portfolio::new(transactions())
+            .classifier(classifier)
+            .report(2021)
+            .await;
+        assert!(strict
+            .unwrap_err()
+            .to_string()
+            .starts_with("sell with a negative value"));
+
+        let lenient = Portfolio::new(transactions())
+            .classifier(classifier)
+            .strictness(Strictness::Lenient)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(lenient.profit().unwrap(), Money::new(d128::from(50)));
+        assert_eq!(lenient.processed().skipped, 1);
+    }
+
+    #[tokio::test]
+    async fn year_out_of_range() {
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(),
+                "1".to_string(),
+                1,
+                Money::new(d128::from(-500_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(),
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(400_i32)),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::new(stream::iter(transactions));
+        let err = portfolio.report(2025).await.unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "no data for year: 2025 available years: 2020-2021"
+        );
+
+        // a year before the data fails on the first transaction
+        let date = |year| NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+        let transactions = vec![
+            trade(date(2020), "1", 2, -1000),
+            trade(date(2020), "1", -1, 600),
+        ];
+        let pulled = AtomicUsize::new(0);
+        let tr_stream = stream::iter(transactions.into_iter().map(Ok)).inspect(|_| {
+            pulled.fetch_add(1, Ordering::SeqCst);
+        });
+        let err = Portfolio::new(tr_stream).report(2019).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no data for year: 2019 transactions start in: 2020"
+        );
+        assert_eq!(pulled.load(Ordering::SeqCst), 1);
+
+        // a year in the range without sells has no profit
+        let transactions = vec![
+            trade(date(2020), "1", 2, -1000),
+            trade(date(2020), "1", -1, 600),
+            trade(date(2021), "1", 1, -500),
+            trade(date(2022), "1", -2, 1200),
+        ];
+        let report = Portfolio::new(stream::iter(transactions.into_iter().map(Ok)))
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(report.profit().unwrap(), Money::default());
+    }
+
+    #[tokio::test]
+    async fn loss_year_after_gains() {
+        let from = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let to = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                from,
+                "1".to_string(),
+                2,
+                Money::new(d128::from(-1000_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                from,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(700_i32)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                to,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(400_i32)),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::with_carry_losses(stream::iter(transactions), 1);
+        let report = portfolio.report(2021).await.unwrap();
+
+        assert_eq!(
+            report.adjusted_profit().unwrap(),
+            Money::new(d128::from(-100))
+        )
+    }
+
+    #[tokio::test]
+    async fn unmatched_sell() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::new(d128::from(-500_i32)),
+                "buy".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -3,
+                Money::new(d128::from(1500_i32)),
+                "sell".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::new(stream::iter(transactions)).strictness(Strictness::Lenient);
+        let report = portfolio.report(2021).await.unwrap();
+
+        assert_eq!(
+            report.unmatched_sells(),
+            &[UnmatchedSell {
+                isin: "1".to_string(),
+                order_id: "sell".to_string(),
+                date,
+                missing: 2,
+            }]
+        );
+
+        // a sell of an ISIN without any buys
+        let transactions = vec![Ok(Transaction::new_unchecked(
+            date,
+            "2".to_string(),
+            -4,
+            Money::new(d128::from(800_i32)),
+            "sell".to_string(),
+        ))];
+        let report = Portfolio::new(stream::iter(transactions))
+            .strictness(Strictness::Lenient)
+            .report(2021)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            report.unmatched_sells(),
+            &[UnmatchedSell {
+                isin: "2".to_string(),
+                order_id: "sell".to_string(),
+                date,
+                missing: 4,
+            }]
+        );
+        assert_eq!(report.processed().skipped, 1);
+    }
+
+    #[tokio::test]
+    async fn fixed_decimals() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::new(d128!(-400.00)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128!(500.00)),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::new(stream::iter(transactions));
+        let report = portfolio.report(2021).await.unwrap();
+
+        assert_eq!(report.adjusted_profit().unwrap().to_string(), "amount: 100");
+        assert_eq!(
+            report.adjusted_profit_fixed(2).unwrap().to_string(),
+            "amount: 100.00"
+        );
+    }
+
+    #[tokio::test]
+    async fn next_year_ends_the_report() {
+        let transactions = vec![
+            Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(),
+                "1".to_string(),
+                2,
+                Money::new(d128::from(-1000_i32)),
+                "id".to_string(),
+            ),
+            Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(),
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(400_i32)),
+                "id".to_string(),
+            ),
+            Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2022, 1, 1).unwrap(),
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(700_i32)),
+                "id".to_string(),
+            ),
+        ];
+
+        let pulled = AtomicUsize::new(0);
+        let tr_stream = stream::iter(transactions.clone().into_iter().map(Ok)).inspect(|_| {
+            pulled.fetch_add(1, Ordering::SeqCst);
+        });
+        let report = Portfolio::new(tr_stream).report(2021).await.unwrap();
+        // every row is pulled once, the 2022 transaction only to be peeked
+        assert_eq!(pulled.load(Ordering::SeqCst), transactions.len());
+
+        let without_next = Portfolio::new(stream::iter(transactions.into_iter().take(2).map(Ok)))
+            .report(2021)
+            .await
+            .unwrap();
+
+        // the 2022 transaction only ends the year
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(-100)));
+        assert_eq!(report.profit_map(), without_next.profit_map());
+        assert_eq!(report.processed(), without_next.processed());
+    }
+
+    #[tokio::test]
+    async fn mixed_currencies() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::with_currency(d128::from(-500_i32), "USD".to_string()),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "2".to_string(),
+                1,
+                Money::with_currency(d128::from(-500_i32), "EUR".to_string()),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::new(stream::iter(transactions));
+        let report = portfolio.report(2021).await.unwrap();
+
+        assert_eq!(report.currencies(), vec!["eur", "usd"]);
+        assert!(report.mixed_currencies());
+    }
+
+    #[tokio::test]
+    async fn product_threshold() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::new(d128!(-500)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "2".to_string(),
+                1,
+                Money::new(d128!(-10)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128!(600)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "2".to_string(),
+                -1,
+                Money::new(d128!(10.004)),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::new(stream::iter(transactions));
+        let report = portfolio.report(2021).await.unwrap();
+        let (products, other) = report
+            .profit_by_product_above(&Money::new(d128!(0.01)))
+            .unwrap();
+
+        assert_eq!(products, vec![("1".to_string(), Money::new(d128!(100)))]);
+        assert_eq!(other, Money::new(d128!(0.004)));
+        assert_eq!(report.profit().unwrap(), Money::new(d128!(100.004)));
+    }
+
+    #[tokio::test]
+    async fn positions_as_of() {
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(),
+                "1".to_string(),
+                2,
+                Money::new(d128!(-1000)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2021, 3, 1).unwrap(),
+                "1".to_string(),
+                -1,
+                Money::new(d128!(600)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2021, 9, 1).unwrap(),
+                "1".to_string(),
+                1,
+                Money::new(d128!(-700)),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::new(stream::iter(transactions));
+        let positions = portfolio
+            .positions_as_of(NaiveDate::from_ymd_opt(2021, 6, 30).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions["1"], (1, Money::new(d128!(500))));
+    }
+
+    #[tokio::test]
+    async fn zero_quantity_and_value() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let corporate_action = || Ok(trade(date, "1", 0, 0));
+
+        // before the first buy and after the position is closed
+        let transactions = vec![
+            corporate_action(),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::new(d128!(-500)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128!(600)),
+                "id".to_string(),
+            )),
+            corporate_action(),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                2,
+                Money::new(d128!(-800)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -2,
+                Money::new(d128!(1000)),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::new(stream::iter(transactions)).audit(true);
+        let report = portfolio.report(2021).await.unwrap();
+
+        assert_eq!(report.profit().unwrap(), Money::new(d128!(300)));
+        assert_eq!(report.processed().skipped, 2);
+        for entry in report.audit() {
+            assert!(!entry.before.avg.amount().is_nan(), "{:?}", entry);
+            assert!(!entry.after.avg.amount().is_nan(), "{:?}", entry);
+        }
+    }
+
+    #[tokio::test]
+    async fn disallow_shorts() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::new(d128!(-500)),
+                "buy".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -2,
+                Money::new(d128!(1000)),
+                "sell".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::new(stream::iter(transactions)).disallow_shorts(true);
+        let err = portfolio.report(2021).await.unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "short position isin: 1 order: sell held: 1 sold: 2"
+        );
+    }
+
+    #[tokio::test]
+    async fn total_fees() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                2,
+                Money::new(d128!(-1000)),
+                "id".to_string(),
+            )
+            .with_fee(Money::new(d128!(-2.83)))),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128!(600)),
+                "id".to_string(),
+            )
+            .with_fee(Money::new(d128!(-2.54)))),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128!(600)),
+                "id".to_string(),
+            )),
+        ];
+
+        let portfolio = Portfolio::new(stream::iter(transactions));
+        let report = portfolio.report(2021).await.unwrap();
+
+        assert_eq!(report.total_fees(2021), Money::new(d128!(5.37)));
+        assert_eq!(report.profit().unwrap(), Money::new(d128!(200)));
+    }
+
+    #[tokio::test]
+    async fn reopened_position() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::new(d128!(-500)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128!(600)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::new(d128!(-300)),
+                "id".to_string(),
+            )),
+        ];
+
+        let positions = Portfolio::new(stream::iter(transactions))
+            .positions_as_of(date)
+            .await
+            .unwrap();
+
+        assert_eq!(positions["1"], (1, Money::new(d128!(300))));
+
+        // a short covered by a buy
+        let transactions = vec![
+            trade(date, "1", 2, -200),
+            trade(date, "1", -3, 330),
+            trade(date, "1", 1, -100),
+            trade(date, "1", 1, -100),
+        ];
+        let positions = Portfolio::new(stream::iter(transactions.clone()).map(Ok))
+            .strictness(Strictness::Lenient)
+            .positions_as_of(date)
+            .await
+            .unwrap();
+        assert_eq!(positions["1"], (1, Money::new(d128!(100))));
+
+        let transactions = transactions.into_iter().chain([trade(date, "1", -1, 150)]);
+        let report = Portfolio::new(stream::iter(transactions).map(Ok))
+            .strictness(Strictness::Lenient)
+            .audit(true)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.audit().last().unwrap().profit,
+            Some(Money::new(d128!(50)))
+        );
+        // 2 held of the 3 sold, the covered short and the reopened position
+        assert_eq!(report.profit().unwrap(), Money::new(d128!(80)));
+    }
+
+    #[tokio::test]
+    async fn cancelled_excluded() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        // DeGiro marks the order in the fee or the total
+        let line = |fee: &str, total: &str| {
+            format!(
+                "01-01-2021,12:43,ETF,1,XET,XETA,-1,900,EUR,900,EUR,900,EUR,,{},EUR,{},EUR,id",
+                fee, total
+            )
+        };
+        let cancelled = Transaction::from_csv_line(&line("Cancelled", "900")).unwrap();
+        assert!(cancelled.is_cancelled());
+        let pending = Transaction::from_csv_line(&line("", "Pending")).unwrap();
+        assert!(pending.is_cancelled());
+        assert!(!Transaction::from_csv_line(&line("-2", "898"))
+            .unwrap()
+            .is_cancelled());
+
+        let transactions = || {
+            vec![
+                Ok(Transaction::new_unchecked(
+                    date,
+                    "1".to_string(),
+                    2,
+                    Money::new(d128::from(-1000)),
+                    "id".to_string(),
+                )),
+                Ok(cancelled.clone()),
+                Ok(Transaction::new_unchecked(
+                    date,
+                    "1".to_string(),
+                    -1,
+                    Money::new(d128::from(600)),
+                    "id".to_string(),
+                )),
+            ]
+        };
+
+        let report = Portfolio::new(stream::iter(transactions()))
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(100)));
+
+        let report = Portfolio::new(stream::iter(transactions()))
+            .include_cancelled(true)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(500)));
+    }
+
+    #[test]
+    fn carry_loss_policy() {
+        let report = |profits: Vec<(i32, i64)>, policy| Report {
+            profits: profits
+                .into_iter()
+                .map(|(year, p)| (year, (Money::default(), Money::new(d128::from(p)))))
+                .collect::<HashMap<_, _>>(),
+            year: 2021,
+            carry_loss_policy: policy,
+            ..Default::default()
+        };
+        let unlimited = CarryLossPolicy {
+            forward_years: None,
+            back_years: None,
+        };
+
+        let old_loss = vec![(2010, -100), (2021, 150)];
+        assert_eq!(
+            report(old_loss.clone(), CarryLossPolicy::flat(5))
+                .adjusted_profit()
+                .unwrap(),
+            Money::new(d128::from(150))
+        );
+        assert_eq!(
+            report(old_loss, unlimited).adjusted_profit().unwrap(),
+            Money::new(d128::from(50))
+        );
+
+        let carry_back = CarryLossPolicy {
+            forward_years: None,
+            back_years: Some(1),
+        };
+        assert_eq!(
+            report(vec![(2019, 50), (2020, 80), (2021, -100)], carry_back)
+                .adjusted_profit()
+                .unwrap(),
+            Money::new(d128::from(-20))
+        );
+        assert_eq!(
+            report(vec![(2020, 80), (2021, -100)], unlimited)
+                .adjusted_profit()
+                .unwrap(),
+            Money::new(d128::from(-100))
+        );
+    }
+
+    #[tokio::test]
+    async fn sell_before_buy() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(500)),
+                "sell".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                2,
+                Money::new(d128::from(-1000)),
+                "buy".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "2".to_string(),
+                1,
+                Money::new(d128::from(-1000)),
+                "buy".to_string(),
+            )),
+        ];
+
+        let issues = Portfolio::new(stream::iter(transactions))
+            .validate()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            issues,
+            vec![Issue::SellBeforeBuy {
+                isin: "1".to_string(),
+                order_id: "sell".to_string(),
+                date,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn avg_holding_period() {
+        let date = |month, day| NaiveDate::from_ymd_opt(2021, month, day).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date(1, 1),
+                "1".to_string(),
+                1,
+                Money::new(d128::from(-100)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date(1, 11),
+                "1".to_string(),
+                3,
+                Money::new(d128::from(-300)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date(1, 21),
+                "1".to_string(),
+                -4,
+                Money::new(d128::from(400)),
+                "id".to_string(),
+            )),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .report(2021)
+            .await
+            .unwrap();
+
+        // (1 * 20 + 3 * 10) / 4
+        assert_eq!(
+            report.avg_holding_period(),
+            vec![("1".to_string(), d128!(12.5))]
+        );
+    }
+
+    #[tokio::test]
+    async fn annual_exemption() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::new(d128::from(-500)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(2000)),
+                "id".to_string(),
+            )),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .annual_exemption(Money::new(d128::from(1000)))
+            .report(2021)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            report.adjusted_profit().unwrap(),
+            Money::new(d128::from(1500))
+        );
+        assert_eq!(
+            report.taxable_profit().unwrap(),
+            Money::new(d128::from(500))
+        );
+    }
+
+    #[test]
+    fn years() {
+        let report = Report {
+            profits: [2021, 2019, 2020]
+                .iter()
+                .map(|year| (*year, Default::default()))
+                .collect(),
+            year: 2021,
+            ..Default::default()
+        };
+
+        assert_eq!(report.years(), vec![2019, 2020, 2021]);
+    }
+
+    #[tokio::test]
+    async fn gains_and_losses() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                2,
+                Money::new(d128::from(-1000)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(700)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(400)),
+                "id".to_string(),
+            )),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .report(2021)
+            .await
+            .unwrap();
+
+        assert_eq!(report.gains(2021), Money::new(d128::from(200)));
+        assert_eq!(report.losses(2021), Money::new(d128::from(-100)));
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(100)));
+        assert_eq!(
+            report.split_by_sign(2021),
+            (Money::new(d128::from(200)), Money::new(d128::from(100)))
+        );
+        assert_eq!(report.gains(2020), Money::default());
+    }
+
+    #[tokio::test]
+    async fn report_until() {
+        let date = |month| NaiveDate::from_ymd_opt(2021, month, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date(1),
+                "1".to_string(),
+                2,
+                Money::new(d128::from(-1000)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date(3),
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(600)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date(9),
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(900)),
+                "id".to_string(),
+            )),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .report_until(NaiveDate::from_ymd_opt(2021, 6, 30).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(100)));
+    }
+
+    #[tokio::test]
+    async fn processed_count() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                2,
+                Money::new(d128::from(-1000)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                0,
+                Money::default(),
+                "id".to_string(),
+            )),
+            Err(anyhow::anyhow!("invalid row")),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(600)),
+                "id".to_string(),
+            )),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .strictness(Strictness::Lenient)
+            .report(2021)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            report.processed(),
+            Processed {
+                buys: 1,
+                sells: 1,
+                skipped: 2,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn separate_cost_rows() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let cost = |order_id: &str| {
+            let mut tr = Transaction::new_unchecked(
+                date,
+                "".to_string(),
+                0,
+                Money::new(d128::from(-5)),
+                order_id.to_string(),
+            );
+            tr.product = "DEGIRO Transaction Costs".to_string();
+            tr
+        };
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                1,
+                Money::new(d128::from(-500)),
+                "buy".to_string(),
+            )),
+            Ok(cost("buy")),
+            Ok(cost("sell")),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(600)),
+                "sell".to_string(),
+            )),
+        ];
+
+        let report = Portfolio::new(crate::fold_costs(stream::iter(transactions)))
+            .report(2021)
+            .await
+            .unwrap();
+
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(90)));
+        // the costs are in the values
+        assert_eq!(report.total_fees(2021), Money::default());
+    }
+
+    #[tokio::test]
+    async fn separate_cost_rows_count_once() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let order = |quantity, value, order_id: &str| Transaction {
+            order_id: order_id.to_string(),
+            total: Some(Money::new(d128::from(value) - d128::from(1))),
+            ..trade(date, "1", quantity, value).with_fee(Money::new(d128::from(-1)))
+        };
+        let cost = |order_id: &str| Transaction {
+            product: "DEGIRO Transaction Costs".to_string(),
+            order_id: order_id.to_string(),
+            ..trade(date, "", 0, -5)
+        };
+        let transactions = || {
+            crate::fold_costs(stream::iter(vec![
+                Ok(order(1, -500, "buy")),
+                Ok(cost("buy")),
+                Ok(cost("sell")),
+                Ok(order(-1, 600, "sell")),
+            ]))
+        };
+
+        let report = Portfolio::new(transactions())
+            .capitalize_fees(true)
+            .check_totals(true)
+            .report(2021)
+            .await
+            .unwrap();
+
+        // 600 - 5 - (500 + 5 + 1), only the buy fee is capitalized
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(89)));
+        assert_eq!(
+            report.net_cash_flow(2021).unwrap(),
+            Money::new(d128::from(88))
+        );
+        assert_eq!(report.total_fees(2021), Money::new(d128::from(2)));
+        assert!(report.warnings().is_empty(), "{:?}", report.warnings());
+
+        let report = Portfolio::new(transactions())
+            .style(ReportStyle::DegiroPnl)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(88)));
+    }
+
+    #[tokio::test]
+    async fn degiro_pnl_fee_above_value() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let transactions = vec![
+            Ok(trade(date, "1", 2, -100).with_fee(Money::new(d128::from(-1)))),
+            Ok(trade(date, "1", -1, 2).with_fee(Money::new(d128::from(-3)))),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .style(ReportStyle::DegiroPnl)
+            .report(2021)
+            .await
+            .unwrap();
+
+        // the sell nets -1 against an average of 50.5
+        assert_eq!(report.processed().buys, 1);
+        assert_eq!(report.processed().sells, 1);
+        assert_eq!(report.profit().unwrap(), Money::new(d128!(-51.5)));
+    }
+
+    #[tokio::test]
+    async fn accrued_interest() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let mut paid = trade(date, "bond", 0, -15);
+        paid.product = "Accrued interest".to_string();
+        let mut received = trade(date, "bond", 0, 25);
+        received.product = "Accrued interest".to_string();
+
+        let transactions = vec![
+            Ok(trade(date, "bond", 10, -1000)),
+            Ok(paid),
+            Ok(received),
+            Ok(trade(date, "bond", -10, 1100)),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .report(2021)
+            .await
+            .unwrap();
+
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(100)));
+        assert_eq!(report.accrued_interest(2021), Money::new(d128::from(10)));
+    }
+
+    #[tokio::test]
+    async fn specific_lots() {
+        let date = |month| NaiveDate::from_ymd_opt(2021, month, 1).unwrap();
+        let transactions = || {
+            vec![
+                Ok(Transaction::new_unchecked(
+                    date(1),
+                    "1".to_string(),
+                    1,
+                    Money::new(d128::from(-100)),
+                    "cheap".to_string(),
+                )),
+                Ok(Transaction::new_unchecked(
+                    date(2),
+                    "1".to_string(),
+                    1,
+                    Money::new(d128::from(-200)),
+                    "expensive".to_string(),
+                )),
+                Ok(Transaction::new_unchecked(
+                    date(3),
+                    "1".to_string(),
+                    -1,
+                    Money::new(d128::from(250)),
+                    "sell".to_string(),
+                )),
+            ]
+        };
+        let profit = |method| async {
+            Portfolio::new(stream::iter(transactions()))
+                .cost_basis(method)
+                .report(2021)
+                .await
+                .unwrap()
+                .profit()
+                .unwrap()
+        };
+
+        let lots = vec![("sell".to_string(), vec!["expensive".to_string()])];
+        let specific = CostBasisMethod::Specific(lots.into_iter().collect());
+        assert_eq!(profit(specific).await, Money::new(d128::from(50)));
+        assert_eq!(
+            profit(CostBasisMethod::Fifo).await,
+            Money::new(d128::from(150))
+        );
+        assert_eq!(
+            profit(CostBasisMethod::Average).await,
+            Money::new(d128::from(100))
+        );
+    }
+
+    #[tokio::test]
+    async fn profit_map() {
+        let date = |year| NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date(2020),
+                "1".to_string(),
+                3,
+                Money::new(d128::from(-300)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date(2020),
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(150)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date(2021),
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(80)),
+                "id".to_string(),
+            )),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .report(2021)
+            .await
+            .unwrap();
+        let map = report.profit_map();
+
+        assert_eq!(map.keys().copied().collect::<Vec<_>>(), vec![2020, 2021]);
+        assert_eq!(
+            map[&2020],
+            YearProfit {
+                gains: Money::new(d128::from(50)),
+                losses: Money::default(),
+            }
+        );
+        assert_eq!(map[&2021].losses, Money::new(d128::from(-20)));
+    }
+
+    #[tokio::test]
+    async fn audit_log() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                2,
+                Money::new(d128::from(-1000)),
+                "buy".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                0,
+                Money::default(),
+                "skipped".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(600)),
+                "sell".to_string(),
+            )),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .audit(true)
+            .report(2021)
+            .await
+            .unwrap();
+        let position = |qty, total, avg| Position {
+            qty,
+            total: Money::new(d128::from(total)),
+            avg: Money::new(d128::from(avg)),
+        };
+
+        assert_eq!(
+            report.audit(),
+            &[
+                AuditEntry {
+                    isin: "1".to_string(),
+                    order_id: "buy".to_string(),
+                    date,
+                    r#type: TransactionType::Buy,
+                    before: Position::default(),
+                    after: position(2, 1000, 500),
+                    profit: None,
+                },
+                AuditEntry {
+                    isin: "1".to_string(),
+                    order_id: "sell".to_string(),
+                    date,
+                    r#type: TransactionType::Sell,
+                    before: position(2, 1000, 500),
+                    after: position(1, 500, 500),
+                    profit: Some(Money::new(d128::from(100))),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_mismatch() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let mut correction = Transaction::new_unchecked(
+            date,
+            "1".to_string(),
+            1,
+            Money::new(d128::from(-500)),
+            "correction".to_string(),
+        );
+        correction.quantity = -1;
+        assert!(correction.checked_type().is_err());
+
+        let report = Portfolio::new(stream::iter(vec![Ok(correction)]))
+            .report(2021)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            report.warnings(),
+            &[Warning::SignMismatch {
+                isin: "1".to_string(),
+                order_id: "correction".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn return_pct_by_product() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                2,
+                Money::new(d128::from(-1000)),
+                "id".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(600)),
+                "id".to_string(),
+            )),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .report(2021)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            report.return_pct_by_product(),
+            vec![("1".to_string(), d128::from(20))]
+        );
+    }
+
+    #[tokio::test]
+    async fn exclude_orders() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+
+        let transactions = vec![
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                2,
+                Money::new(d128::from(-1000)),
+                "buy".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(900)),
+                "wrong".to_string(),
+            )),
+            Ok(Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(600)),
+                "sell".to_string(),
+            )),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .exclude_orders(vec!["wrong".to_string()])
+            .report(2021)
+            .await
+            .unwrap();
+
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(100)));
+    }
+
+    #[test]
+    fn estimate_tax() {
+        let report = |profit: i32| Report {
+            profits: vec![(2021, (Money::new(d128::from(profit)), Money::default()))]
+                .into_iter()
+                .collect(),
+            year: 2021,
+            ..Default::default()
+        };
+
+        let flat = TaxSchedule::flat(d128!(0.3));
+        assert_eq!(
+            report(1500).estimate_tax(&flat).unwrap(),
+            Money::new(d128::from(450))
+        );
+        assert_eq!(report(-1500).estimate_tax(&flat).unwrap(), Money::default());
+
+        let brackets = TaxSchedule::brackets(vec![
+            (d128::from(0), d128!(0.1)),
+            (d128::from(1000), d128!(0.3)),
+        ]);
+        assert_eq!(
+            report(1500).estimate_tax(&brackets).unwrap(),
+            Money::new(d128::from(250))
+        );
+    }
+
+    #[tokio::test]
+    async fn open_positions() {
+        let date = |day| NaiveDate::from_ymd_opt(2021, 1, day).unwrap();
+
+        let transactions = vec![
+            Ok(trade(date(1), "1", 1, -100)),
+            Ok(trade(date(2), "2", 1, -100)),
+            Ok(trade(date(3), "1", -1, 150)),
+            Ok(trade(date(4), "2", -1, 150)),
+        ];
+
+        let counts = Portfolio::new(stream::iter(transactions))
+            .open_positions()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            counts,
+            vec![(date(1), 1), (date(2), 2), (date(3), 1), (date(4), 0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_years() {
+        let date = |year| NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+        let transactions = vec![
+            Ok(trade(date(2020), "1", 4, -1000)),
+            Ok(trade(date(2020), "1", -1, 300)),
+            Ok(trade(date(2021), "1", -1, 200)),
+            Ok(trade(date(2022), "1", -1, 350)),
+            Ok(trade(date(2022), "1", -1, 150)),
+        ];
+
+        let pulled = AtomicUsize::new(0);
+        let tr_stream = stream::iter(transactions).inspect(|_| {
+            pulled.fetch_add(1, Ordering::SeqCst);
+        });
+        let years = Portfolio::new(tr_stream).stream_years();
+        futures::pin_mut!(years);
+
+        let profit = |gains: i32, losses: i32| YearProfit {
+            gains: Money::new(d128::from(gains)),
+            losses: Money::new(d128::from(losses)),
+        };
+        // 2020 is done once the 2021 sell is peeked
+        assert_eq!(years.next().await.unwrap().unwrap(), (2020, profit(50, 0)));
+        assert_eq!(pulled.load(Ordering::SeqCst), 3);
+        assert_eq!(years.next().await.unwrap().unwrap(), (2021, profit(0, -50)));
+        assert_eq!(pulled.load(Ordering::SeqCst), 4);
+        assert_eq!(
+            years.next().await.unwrap().unwrap(),
+            (2022, profit(100, -100))
+        );
+        assert!(years.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn since() {
+        let transactions = vec![
+            Ok(trade(
+                NaiveDate::from_ymd_opt(2020, 6, 1).unwrap(),
+                "1",
+                2,
+                -200,
+            )),
+            Ok(trade(
+                NaiveDate::from_ymd_opt(2021, 2, 1).unwrap(),
+                "1",
+                -1,
+                150,
+            )),
+            Ok(trade(
+                NaiveDate::from_ymd_opt(2021, 3, 1).unwrap(),
+                "1",
+                -1,
+                130,
+            )),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .since(NaiveDate::from_ymd_opt(2021, 3, 1).unwrap())
+            .report(2021)
+            .await
+            .unwrap();
+        // the 2020 buy is the basis of the reported sell
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(30)));
+        assert_eq!(report.gains(2021), Money::new(d128::from(30)));
+    }
+
+    #[tokio::test]
+    async fn realized_profits() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let transactions = vec![
+            trade(date, "1", 2, -200),
+            trade(date, "1", -1, 150),
+            trade(date, "1", -1, 80),
+        ];
+
+        let sells: Vec<_> = Portfolio::new(stream::iter(transactions.clone()).map(Ok))
+            .realized_profits()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(
+            sells,
+            vec![
+                (transactions[1].clone(), Money::new(d128::from(50))),
+                (transactions[2].clone(), Money::new(d128::from(-20))),
+            ]
+        );
+    }
+
+    #[test]
+    fn remaining_carry_loss() {
+        let mut profits = HashMap::new();
+        profits.insert(2020, (Money::default(), Money::new(d128::from(-1000))));
+        profits.insert(2021, (Money::new(d128::from(300)), Money::default()));
+        let report = Report {
+            profits,
+            carry_loss_policy: CarryLossPolicy::flat(5),
+            year: 2021,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            report.remaining_carry_loss().unwrap(),
+            Money::new(d128::from(-700))
+        );
+        assert_eq!(
+            report.adjusted_profit().unwrap(),
+            Money::new(d128::from(-700))
+        );
+    }
+
+    #[tokio::test]
+    async fn annualized_return() {
+        let transactions = vec![
+            Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(),
+                "1".to_string(),
+                2,
+                Money::new(d128::from(-200)),
+                "a".to_string(),
+            ),
+            Transaction::new_unchecked(
+                NaiveDate::from_ymd_opt(2021, 6, 30).unwrap(),
+                "1".to_string(),
+                -1,
+                Money::new(d128::from(110)),
+                "b".to_string(),
+            ),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions).map(Ok))
+            .report(2021)
+            .await
+            .unwrap();
+        let lot = &report.closed_lots()[0];
+        assert_eq!((lot.sold - lot.bought).num_days(), 180);
+        assert_eq!(lot.cost, Money::new(d128::from(100)));
+        assert_eq!(lot.proceeds, Money::new(d128::from(110)));
+
+        // 1.1 ^ (365 / 180) - 1
+        let rate = lot.annualized_return().unwrap();
+        assert_eq!(rate.quantize(d128!(0.0001)), d128!(0.2132));
+        assert_eq!(
+            report.annualized_return_by_product(),
+            vec![("1".to_string(), rate)]
+        );
+    }
+
+    #[test]
+    fn take_year() {
+        let lot = |year| ClosedLot {
+            isin: "1".to_string(),
+            bought: NaiveDate::from_ymd_opt(2019, 1, 1).unwrap(),
+            sold: NaiveDate::from_ymd_opt(year, 1, 1).unwrap(),
+            qty: 1,
+            cost: Money::new(d128::from(100)),
+            proceeds: Money::new(d128::from(110)),
+        };
+        let mut report = Report {
+            closed_lots: vec![lot(2020), lot(2021)],
+            ..Default::default()
+        };
+
+        report.take_year(2020);
+        assert_eq!(report.closed_lots(), &[lot(2021)]);
+    }
+
+    #[tokio::test]
+    async fn local_profits() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let usd = |amount: i32| Money::with_currency(d128::from(amount), "usd".to_string());
+        let transactions = vec![
+            Ok(Transaction {
+                local_value: usd(-200),
+                ..trade(date, "US0378331005", 2, -180).assume_currency("eur")
+            }),
+            Ok(Transaction {
+                local_value: usd(120),
+                ..trade(date, "US0378331005", -1, 100).assume_currency("eur")
+            }),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .local_profits(true)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.profit_by_product(),
+            vec![(
+                "US0378331005".to_string(),
+                Money::with_currency(d128::from(10), "eur".to_string())
+            )]
+        );
+        assert_eq!(
+            report.local_profit_by_product(),
+            vec![(
+                "US0378331005".to_string(),
+                Money::with_currency(d128::from(20), "usd".to_string())
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn sell_into_short() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let transactions = vec![
+            Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                2,
+                Money::new(d128::from(-200)),
+                "buy".to_string(),
+            ),
+            Transaction::new_unchecked(
+                date,
+                "1".to_string(),
+                -3,
+                Money::new(d128::from(450)),
+                "sell".to_string(),
+            ),
+        ];
+
+        let date = NaiveDate::from_ymd_opt(2021, 12, 31).unwrap();
+        let portfolio = Portfolio::new(stream::iter(transactions.clone()).map(Ok))
+            .strictness(Strictness::Lenient);
+        let positions = portfolio.positions_as_of(date).await.unwrap();
+        // the short opens at the price of the sell
+        assert_eq!(positions["1"], (-1, Money::new(d128::from(150))));
+
+        let report = Portfolio::new(stream::iter(transactions.clone()).map(Ok))
+            .strictness(Strictness::Lenient)
+            .report(2021)
+            .await
+            .unwrap();
+        // only the 2 shares held are realized
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(100)));
+
+        // a buy covers the short at a profit of 150 - 120, without opening a long
+        let covered: Vec<_> = transactions
+            .into_iter()
+            .chain([trade(date, "1", 1, -120)])
+            .collect();
+        let positions = Portfolio::new(stream::iter(covered.clone()).map(Ok))
+            .strictness(Strictness::Lenient)
+            .positions_as_of(date)
+            .await
+            .unwrap();
+        assert!(positions.is_empty());
+
+        // the next long is the only lot the last sell closes
+        let transactions = covered
+            .into_iter()
+            .chain([trade(date, "1", 1, -100), trade(date, "1", -1, 130)]);
+        let report = Portfolio::new(stream::iter(transactions).map(Ok))
+            .strictness(Strictness::Lenient)
+            .cost_basis(CostBasisMethod::Fifo)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(report.profit().unwrap(), Money::new(d128::from(160)));
+    }
+
+    #[tokio::test]
+    async fn open_cost_basis() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let transactions = vec![
+            Ok(trade(date, "1", 3, -300).assume_currency("eur")),
+            Ok(trade(date, "2", 1, -50).assume_currency("eur")),
+            Ok(trade(date, "1", -1, 120).assume_currency("eur")),
+            Ok(trade(date, "2", -1, 40).assume_currency("eur")),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .report(2021)
+            .await
+            .unwrap();
+        // two shares of 1 are left at 100 each
+        assert_eq!(
+            report.open_cost_basis().unwrap(),
+            Money::with_currency(d128::from(200), "eur".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn currency_overrides() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let transactions = vec![
+            Ok(trade(date, "US", 1, -100)),
+            Ok(trade(date, "US", -1, 130)),
+            Ok(trade(date, "NL", 1, -10)),
+        ];
+
+        let mut overrides = HashMap::new();
+        overrides.insert("US".to_string(), "USD".to_string());
+        let report = Portfolio::new(stream::iter(transactions))
+            .currency_overrides(overrides)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.profit().unwrap(),
+            Money::with_currency(d128::from(30), "usd".to_string())
+        );
+        assert_eq!(report.currencies(), vec!["usd".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn avg_exchange_rate() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let usd = |amount: i32| Money::with_currency(d128::from(amount), "usd".to_string());
+        let transactions = vec![
+            Ok(Transaction {
+                local_value: usd(-110),
+                ..trade(date, "US0378331005", 1, -110).with_exchange_rate(d128!(1.1))
+            }),
+            Ok(Transaction {
+                local_value: usd(-360),
+                ..trade(date, "US0378331005", 3, -360).with_exchange_rate(d128!(1.2))
+            }),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(report.avg_exchange_rate(2021, "USD"), Some(d128!(1.175)));
+        assert_eq!(report.avg_exchange_rate(2020, "usd"), None);
+    }
+
+    #[tokio::test]
+    async fn missing_years() {
+        let date = |year| NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+        let transactions = vec![
+            Ok(trade(date(2019), "1", 2, -200)),
+            Ok(trade(date(2021), "1", -1, 150)),
+        ];
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(report.warnings(), &[Warning::MissingYears(vec![2020])]);
+        assert_eq!(
+            report.warnings()[0].to_string(),
+            "no transactions in the years: [2020]"
+        );
+    }
+
+    #[test]
+    fn to_toml() {
+        let money = |amount: i32| Money::with_currency(d128::from(amount), "eur".to_string());
+        let mut profits = HashMap::new();
+        profits.insert(2020, (money(0), money(-30)));
+        profits.insert(2021, (money(150), money(-20)));
+        let report = Report {
+            profits,
+            year: 2021,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            report.to_toml().unwrap(),
+            r#"[years.2020]
+gains = "0 eur"
+losses = "-30 eur"
+net = "-30 eur"
+
+[years.2021]
+gains = "150 eur"
+losses = "-20 eur"
+net = "130 eur"
+"#
+        );
+    }
+
+    #[tokio::test]
+    async fn round_avg() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let transactions = || {
+            stream::iter(vec![
+                Ok(trade(date, "1", 3, -100)),
+                Ok(trade(date, "1", -1, 40)),
+            ])
+        };
+
+        let unrounded = Portfolio::new(transactions()).report(2021).await.unwrap();
+        assert_eq!(
+            unrounded.profit().unwrap(),
+            Money::new(d128!(6.66666666666666666666666666666667))
+        );
+
+        // the average of 33.33 per share
+        let rounded = Portfolio::new(transactions())
+            .round_avg(2, Rounding::HalfUp)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(rounded.profit().unwrap(), Money::new(d128!(6.67)));
+    }
+
+    #[tokio::test]
+    async fn drip() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        // the dividend of one share lowers the average from 25 to 20
+        let transactions = || {
+            stream::iter(vec![
+                Ok(trade(date, "1", 4, -100)),
+                Ok(trade(date, "1", 1, 0)),
+                Ok(trade(date, "1", -4, 120)),
+            ])
+        };
+
+        let zero_cost = Portfolio::new(transactions()).report(2021).await.unwrap();
+        assert_eq!(zero_cost.profit().unwrap(), Money::new(d128::from(40)));
+        assert_eq!(zero_cost.processed().buys, 2);
+
+        let excluded = Portfolio::new(transactions())
+            .drip(DripTreatment::Exclude)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(excluded.profit().unwrap(), Money::new(d128::from(20)));
+        assert_eq!(excluded.processed().skipped, 1);
+    }
+
+    #[tokio::test]
+    async fn warn_loss() {
+        let date = NaiveDate::from_ymd_opt(2021, 3, 1).unwrap();
+        let transactions = stream::iter(vec![
+            Ok(trade(date, "small", 1, -100)),
+            Ok(trade(date, "large", 1, -100)),
+            Ok(trade(date, "small", -1, 90)),
+            Ok(trade(date, "large", -1, 40)),
+        ]);
+
+        let report = Portfolio::new(transactions)
+            .warn_loss(Money::new(d128::from(50)))
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.warnings(),
+            &[Warning::LargeLoss {
+                isin: "large".to_string(),
+                date,
+                loss: Money::new(d128::from(-60)),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn net_cash_flow() {
+        let date = |month| NaiveDate::from_ymd_opt(2021, month, 1).unwrap();
+        let fee = Money::new(d128::from(-2));
+        let dividend = Transaction {
+            product: "dividend".to_string(),
+            ..trade(date(4), "1", 0, 15)
+        };
+        let transactions = stream::iter(vec![
+            Ok(trade(date(1), "1", 10, -1000).with_fee(fee.clone())),
+            Ok(trade(date(2), "2", 5, -500)),
+            Ok(trade(date(3), "1", -10, 900).with_fee(fee)),
+            Ok(dividend),
+        ]);
+
+        let report = Portfolio::new(transactions)
+            .classifier(|tr| match tr.product() {
+                "dividend" => TransactionType::Other,
+                _ => tr.r#type(),
+            })
+            .report(2021)
+            .await
+            .unwrap();
+        // the loss of 100 on the sell does not matter, the cost of the open buy does
+        assert_eq!(
+            report.net_cash_flow(2021).unwrap(),
+            Money::new(d128::from(-589))
+        );
+        assert_eq!(report.net_cash_flow(2020).unwrap(), Money::default());
+    }
+
+    #[tokio::test]
+    async fn profit_by_category() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let transactions = stream::iter(vec![
+            Ok(trade(date, "etf1", 1, -100)),
+            Ok(trade(date, "etf2", 1, -100)),
+            Ok(trade(date, "stock", 1, -100)),
+            Ok(trade(date, "unknown", 1, -100)),
+            Ok(trade(date, "etf1", -1, 110)),
+            Ok(trade(date, "etf2", -1, 120)),
+            Ok(trade(date, "stock", -1, 70)),
+            Ok(trade(date, "unknown", -1, 105)),
+        ]);
+        let categories = [("etf1", "ETF"), ("etf2", "ETF"), ("stock", "Stock")]
+            .iter()
+            .map(|(isin, category)| (isin.to_string(), category.to_string()))
+            .collect();
+
+        let report = Portfolio::new(transactions)
+            .categories(categories)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.profit_by_category().unwrap(),
+            vec![
+                ("ETF".to_string(), Money::new(d128::from(30))),
+                ("Stock".to_string(), Money::new(d128::from(-30))),
+                ("other".to_string(), Money::new(d128::from(5))),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn extremes() {
+        let date = |day| NaiveDate::from_ymd_opt(2021, 1, day).unwrap();
+        let transactions = stream::iter(vec![
+            Ok(trade(date(1), "1", 3, -300)),
+            Ok(trade(date(1), "2", 2, -200)),
+            Ok(trade(date(2), "1", -1, 150)),
+            Ok(trade(date(3), "2", -1, 20)),
+            Ok(trade(date(4), "1", -1, 180)),
+            Ok(trade(date(5), "2", -1, 90)),
+        ]);
+
+        let report = Portfolio::new(transactions).report(2021).await.unwrap();
+        assert_eq!(
+            report.extremes(2021),
+            (
+                Some(("1".to_string(), date(4), Money::new(d128::from(80)))),
+                Some(("2".to_string(), date(3), Money::new(d128::from(-80)))),
+            )
+        );
+        assert_eq!(report.extremes(2020), (None, None));
+    }
+
+    #[tokio::test]
+    async fn partial_sells() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let mut transactions = vec![Ok(trade(date, "1", 30, -100))];
+        transactions.extend((0..10).map(|_| Ok(trade(date, "1", -1, 4))));
+
+        let report = Portfolio::new(stream::iter(transactions))
+            .audit(true)
+            .report(2021)
+            .await
+            .unwrap();
+        let after = &report.audit().last().unwrap().after;
+        let avg = Money::new(d128::from(100) / d128::from(30));
+        let mut total = avg.clone();
+        total.mul(20).unwrap();
+        assert_eq!(
+            after,
+            &Position {
+                qty: 20,
+                total,
+                avg
+            }
+        );
+
+        let mut remaining = after.total.clone();
+        remaining.div(after.qty).unwrap();
+        assert_eq!(remaining, after.avg);
+    }
+
+    #[tokio::test]
+    async fn check_totals() {
+        let line = |total: &str| {
+            format!(
+                "07-07-2021,12:43,ETF,1,XET,XETA,9,307.50,EUR,-2767.50,EUR,-2767.50,EUR,,-2.83,EUR,{},EUR,buy",
+                total
+            )
+        };
+        let consistent = Transaction::from_csv_line(&line("-2770.33")).unwrap();
+        assert_eq!(consistent.total(), Some(&Money::new(d128!(-2770.33))));
+        assert_eq!(Transaction::from_csv_line(&line("")).unwrap().total(), None);
+        assert!(Transaction::from_csv_line(&line("abc")).is_err());
+        let inconsistent = Transaction::from_csv_line(&line("-2780.33")).unwrap();
+
+        let report = Portfolio::new(stream::iter(vec![Ok(consistent), Ok(inconsistent)]))
+            .check_totals(true)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.warnings(),
+            &[Warning::TotalMismatch {
+                isin: "1".to_string(),
+                order_id: "buy".to_string(),
+                total: Money::new(d128!(-2780.33)),
+                expected: Money::new(d128!(-2770.33)),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn to_json() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        // selling more than was bought
+        let transactions = stream::iter(vec![
+            Ok(trade(date, "1", 1, -100)),
+            Ok(trade(date, "1", -2, 300)),
+        ]);
+        let report = Portfolio::new(transactions)
+            .strictness(Strictness::Lenient)
+            .report(2021)
+            .await
+            .unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["report"]["year"], 2021);
+        assert_eq!(json["report"]["profit"], "50");
+        assert_eq!(json["report"]["years"]["2021"]["gains"], "50");
+        assert_eq!(
+            json["warnings"],
+            serde_json::json!([{
+                "kind": "unmatched-sell",
+                "detail": {
+                    "isin": "1",
+                    "order_id": "id",
+                    "date": "2021-01-01",
+                    "missing": 1,
+                },
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn turnover() {
+        let date = |year| NaiveDate::from_ymd_opt(year, 6, 1).unwrap();
+        let transactions = stream::iter(vec![
+            Ok(trade(date(2020), "1", 10, -1000)),
+            Ok(trade(date(2021), "1", -5, 600)),
+            Ok(trade(date(2021), "1", 2, -250)),
+            Ok(trade(date(2021), "1", 0, 0)),
+            Ok(trade(date(2021), "1", -7, 700)),
+        ]);
+
+        let report = Portfolio::new(transactions).report(2021).await.unwrap();
+        assert_eq!(report.turnover(2021).unwrap(), Money::new(d128::from(1550)));
+        assert_eq!(report.turnover(2020).unwrap(), Money::new(d128::from(1000)));
+    }
+
+    #[tokio::test]
+    async fn sell_currency_mismatch() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let transactions = || {
+            stream::iter(vec![
+                Ok(trade(date, "1", 2, -100).assume_currency("EUR")),
+                Ok(trade(date, "1", -1, 60).assume_currency("USD")),
+            ])
+        };
+
+        let err = Portfolio::new(transactions())
+            .report(2021)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "sell currency usd differs from the position currency eur isin: 1 order: id"
+        );
+
+        let report = Portfolio::new(transactions())
+            .strictness(Strictness::Lenient)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(report.processed().sells, 0);
+    }
+
+    #[tokio::test]
+    async fn capitalize_fees() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let transactions = || {
+            stream::iter(vec![
+                Ok(trade(date, "1", 2, -100).with_fee(Money::new(d128::from(-4)))),
+                Ok(trade(date, "1", -1, 60).with_fee(Money::new(d128::from(-2)))),
+            ])
+        };
+
+        let plain = Portfolio::new(transactions())
+            .audit(true)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(plain.audit()[0].after.avg, Money::new(d128::from(50)));
+        assert_eq!(plain.profit().unwrap(), Money::new(d128::from(10)));
+
+        // the fee of the sell stays out of the basis
+        let capitalized = Portfolio::new(transactions())
+            .capitalize_fees(true)
+            .audit(true)
+            .report(2021)
+            .await
+            .unwrap();
+        assert_eq!(capitalized.audit()[0].after.avg, Money::new(d128::from(52)));
+        assert_eq!(capitalized.profit().unwrap(), Money::new(d128::from(8)));
+    }
+
+    #[tokio::test]
+    async fn with_year() {
+        let date = |year| NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+        let transactions = || {
+            stream::iter(vec![
+                Ok(trade(date(2020), "1", 3, -300)),
+                Ok(trade(date(2020), "1", -1, 60)),
+                Ok(trade(date(2021), "1", -1, 250)),
+            ])
+        };
+
+        let report = Portfolio::with_carry_losses(transactions(), 1)
+            .report(2021)
+            .await
+            .unwrap();
+        let earlier = Portfolio::with_carry_losses(transactions(), 1)
+            .report(2020)
+            .await
+            .unwrap();
+        let retargeted = report.with_year(2020).unwrap();
+        assert_eq!(retargeted.year(), 2020);
+        assert_eq!(retargeted.profit().unwrap(), Money::new(d128::from(-40)));
+        assert_eq!(retargeted.profit().unwrap(), earlier.profit().unwrap());
+        assert_eq!(
+            retargeted.adjusted_profit().unwrap(),
+            earlier.adjusted_profit().unwrap()
+        );
+        assert_eq!(
+            report.adjusted_profit().unwrap(),
+            Money::new(d128::from(110))
+        );
+        assert!(report.with_year(2019).is_err());
     }
 }
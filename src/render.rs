@@ -0,0 +1,230 @@
+use crate::money::{Locale, Rounding};
+use crate::portfolio::Report;
+use crate::taxform::TaxForm;
+use crate::Money;
+use chrono::NaiveDate;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+/// Writes a report in one output format
+pub trait ReportRenderer {
+    fn render(&self, report: &Report, w: &mut dyn Write) -> io::Result<()>;
+}
+
+/// Which profit of a report is rendered as text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profit {
+    /// `Report::profit`
+    Unadjusted,
+    /// `Report::adjusted_profit`
+    Adjusted,
+    /// `Report::taxable_profit`
+    Taxable,
+}
+
+impl Profit {
+    pub fn of(self, report: &Report) -> anyhow::Result<Money> {
+        match self {
+            Self::Unadjusted => report.profit(),
+            Self::Adjusted => Ok(report.adjusted_profit()?),
+            Self::Taxable => Ok(report.taxable_profit()?),
+        }
+    }
+}
+
+/// `report: <profit>` rounded to two decimals,
+/// after the gains and losses of the year with `breakdown`
+#[derive(Clone, Debug)]
+pub struct Text {
+    pub profit: Profit,
+    pub rounding: Rounding,
+    pub locale: Locale,
+    /// always prints two decimals, including trailing zeros
+    pub fixed: bool,
+    pub breakdown: bool,
+}
+
+impl Text {
+    /// truncates to two decimals with the `en` separators
+    pub fn new(profit: Profit) -> Self {
+        Self {
+            profit,
+            rounding: Rounding::Truncate,
+            locale: Locale::En,
+            fixed: false,
+            breakdown: false,
+        }
+    }
+
+    /// formats `money` like the amounts of the report
+    pub fn amount(&self, money: &Money) -> String {
+        let mut money = money.round(2, self.rounding);
+        if self.fixed {
+            money = money.fixed(2);
+        }
+        money.format(self.locale)
+    }
+}
+
+impl ReportRenderer for Text {
+    fn render(&self, report: &Report, w: &mut dyn Write) -> io::Result<()> {
+        let year = report.year();
+        if self.breakdown {
+            writeln!(w, "gains: {}", self.amount(&report.gains(year)))?;
+            writeln!(w, "losses: {}", self.amount(&report.losses(year)))?;
+        }
+        let profit = self.profit.of(report).map_err(io::Error::other)?;
+        write!(w, "report: {}", self.amount(&profit))
+    }
+}
+
+/// The profits and warnings as one document, see `Report::to_json`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+impl ReportRenderer for Json {
+    fn render(&self, report: &Report, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(report.to_json().map_err(io::Error::other)?.as_bytes())
+    }
+}
+
+/// The profits of every year, see `Report::to_toml`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Toml;
+
+impl ReportRenderer for Toml {
+    fn render(&self, report: &Report, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(report.to_toml().map_err(io::Error::other)?.as_bytes())
+    }
+}
+
+/// The `TaxForm` of the year as a CSV row under a header line
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Csv;
+
+impl ReportRenderer for Csv {
+    fn render(&self, report: &Report, w: &mut dyn Write) -> io::Result<()> {
+        let form = TaxForm::try_from(report).map_err(io::Error::other)?;
+        let mut csv = csv::Writer::from_writer(w);
+        csv.serialize(form)?;
+        csv.flush()
+    }
+}
+
+/// A table of the gains, losses and net of every year,
+/// with the amounts rounded to two decimals
+#[derive(Clone, Copy, Debug)]
+pub struct Html {
+    pub rounding: Rounding,
+}
+
+impl ReportRenderer for Html {
+    fn render(&self, report: &Report, w: &mut dyn Write) -> io::Result<()> {
+        let cell = |money: &Money| {
+            let money = money.round(2, self.rounding);
+            match money.currency() {
+                Some(currency) => format!("{} {}", money.amount(), currency),
+                None => money.amount().to_string(),
+            }
+        };
+
+        writeln!(w, "<table>")?;
+        writeln!(
+            w,
+            "<tr><th>year</th><th>gains</th><th>losses</th><th>net</th></tr>"
+        )?;
+        for (year, profit) in report.profit_map() {
+            let mut net = profit.gains.clone();
+            net.add(&profit.losses).map_err(io::Error::other)?;
+            writeln!(
+                w,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                year,
+                cell(&profit.gains),
+                cell(&profit.losses),
+                cell(&net)
+            )?;
+        }
+        writeln!(w, "</table>")
+    }
+}
+
+/// writes the `(date, cumulative profit)` rows of `Portfolio::equity_curve`
+/// as CSV under a `date,profit` header line
+pub fn write_equity_curve(curve: &[(NaiveDate, Money)], w: &mut dyn Write) -> io::Result<()> {
+    let mut csv = csv::Writer::from_writer(w);
+    csv.write_record(["date", "profit"])?;
+    for (date, profit) in curve {
+        csv.serialize((date, profit.truncate_trailing_zeros()))?;
+    }
+    csv.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::money::Rounding;
+    use crate::portfolio::Portfolio;
+    use crate::render::{write_equity_curve, Csv, Html, Profit, ReportRenderer, Text};
+    use crate::test::trade;
+    use chrono::NaiveDate;
+    use futures::stream;
+
+    #[tokio::test]
+    async fn renderers() {
+        let date = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        let transactions = stream::iter(vec![
+            Ok(trade(date, "1", 3, -100)),
+            Ok(trade(date, "1", -1, 40)),
+        ]);
+        let report = Portfolio::new(transactions).report(2021).await.unwrap();
+        let render = |renderer: &dyn ReportRenderer| {
+            let mut out = Vec::new();
+            renderer.render(&report, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let text = Text {
+            breakdown: true,
+            ..Text::new(Profit::Unadjusted)
+        };
+        assert_eq!(
+            render(&text),
+            "gains: amount: 6.66\nlosses: amount: 0\nreport: amount: 6.66"
+        );
+        assert_eq!(
+            render(&Csv),
+            "year,total_proceeds,total_cost,net_gain,carried_losses_applied,taxable_gain\n\
+             2021,40,33.33333333333333333333333333333333,6.66666666666666666666666666666667,0,6.66666666666666666666666666666667\n"
+        );
+        assert_eq!(
+            render(&Html {
+                rounding: Rounding::Truncate
+            }),
+            "<table>\n\
+             <tr><th>year</th><th>gains</th><th>losses</th><th>net</th></tr>\n\
+             <tr><td>2021</td><td>6.66</td><td>0</td><td>6.66</td></tr>\n\
+             </table>\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn equity_curve() {
+        let date = |day| NaiveDate::from_ymd_opt(2021, 1, day).unwrap();
+        let transactions = stream::iter(vec![
+            Ok(trade(date(1), "1", 4, -400)),
+            Ok(trade(date(2), "1", -1, 150)),
+            Ok(trade(date(2), "1", -1, 80)),
+            Ok(trade(date(3), "1", 1, -100)),
+            Ok(trade(date(5), "1", -1, 60)),
+        ]);
+        let curve = Portfolio::new(transactions).equity_curve().await.unwrap();
+
+        let mut out = Vec::new();
+        write_equity_curve(&curve, &mut out).unwrap();
+        // the two sells of the 2nd are one row
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "date,profit\n2021-01-02,30\n2021-01-05,-10\n"
+        );
+    }
+}
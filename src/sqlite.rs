@@ -0,0 +1,159 @@
+use crate::money::Money;
+use crate::{Transaction, TIME_FORMAT_SECONDS};
+use chrono::{NaiveDate, NaiveTime};
+use decimal::d128;
+use futures::{stream, Stream};
+use rusqlite::{params, Connection, Row};
+use std::str::FromStr;
+
+// dates are stored as YYYY-MM-DD so the table sorts by them
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Writes `transactions` to the `transactions` table of `conn`,
+/// creating it when missing, to be read back by `read`
+pub fn write(conn: &Connection, transactions: &[Transaction]) -> anyhow::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS transactions (
+            date TEXT NOT NULL,
+            time TEXT,
+            product TEXT NOT NULL,
+            isin TEXT NOT NULL,
+            reference TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            venue TEXT NOT NULL,
+            price TEXT NOT NULL,
+            price_currency TEXT,
+            local_value TEXT NOT NULL,
+            local_value_currency TEXT,
+            value TEXT NOT NULL,
+            value_currency TEXT,
+            fee TEXT,
+            fee_currency TEXT,
+            exchange_rate TEXT,
+            total TEXT,
+            total_currency TEXT,
+            order_id TEXT NOT NULL,
+            cancelled INTEGER NOT NULL
+        )",
+    )?;
+    let mut insert = conn.prepare(
+        "INSERT INTO transactions VALUES
+            (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
+    )?;
+    for tr in transactions {
+        insert.execute(params![
+            tr.date.format(DATE_FORMAT).to_string(),
+            tr.time
+                .map(|time| time.format(TIME_FORMAT_SECONDS).to_string()),
+            tr.product,
+            tr.isin,
+            tr.reference,
+            tr.quantity as i64,
+            tr.venue,
+            tr.price.amount.to_string(),
+            tr.price.currency,
+            tr.local_value.amount.to_string(),
+            tr.local_value.currency,
+            tr.value.amount.to_string(),
+            tr.value.currency,
+            tr.fee.as_ref().map(|fee| fee.amount.to_string()),
+            tr.fee.as_ref().and_then(|fee| fee.currency.clone()),
+            tr.exchange_rate,
+            tr.total.as_ref().map(|total| total.amount.to_string()),
+            tr.total.as_ref().and_then(|total| total.currency.clone()),
+            tr.order_id,
+            tr.cancelled,
+        ])?;
+    }
+    Ok(())
+}
+
+/// Streams the transactions of the `transactions` table from oldest to newest,
+/// like a `CsvStream`
+pub fn read(conn: &Connection) -> anyhow::Result<impl Stream<Item = anyhow::Result<Transaction>>> {
+    let mut select = conn.prepare("SELECT * FROM transactions ORDER BY date, time, rowid")?;
+    let transactions: Vec<_> = select.query_map([], |row| Ok(from_row(row)))?.collect();
+    Ok(stream::iter(transactions.into_iter().map(|tr| {
+        tr.map_err(anyhow::Error::from).and_then(|tr| tr)
+    })))
+}
+
+fn from_row(row: &Row) -> anyhow::Result<Transaction> {
+    let date: String = row.get("date")?;
+    let time: Option<String> = row.get("time")?;
+    Ok(Transaction {
+        date: NaiveDate::parse_from_str(&date, DATE_FORMAT)?,
+        time: time
+            .map(|time| NaiveTime::parse_from_str(&time, TIME_FORMAT_SECONDS))
+            .transpose()?,
+        product: row.get("product")?,
+        isin: row.get("isin")?,
+        reference: row.get("reference")?,
+        quantity: row.get::<_, i64>("quantity")? as isize,
+        venue: row.get("venue")?,
+        price: money(row, "price")?,
+        local_value: money(row, "local_value")?,
+        value: money(row, "value")?,
+        fee: match row.get::<_, Option<String>>("fee")? {
+            Some(_) => Some(money(row, "fee")?),
+            None => None,
+        },
+        exchange_rate: row.get("exchange_rate")?,
+        total: match row.get::<_, Option<String>>("total")? {
+            Some(_) => Some(money(row, "total")?),
+            None => None,
+        },
+        order_id: row.get("order_id")?,
+        cancelled: row.get("cancelled")?,
+    })
+}
+
+// the amount of the `column` with its currency from `<column>_currency`
+fn money(row: &Row, column: &str) -> anyhow::Result<Money> {
+    let amount: String = row.get(column)?;
+    let amount =
+        d128::from_str(&amount).map_err(|_| anyhow::anyhow!("invalid amount: {}", amount))?;
+    Ok(Money {
+        amount,
+        currency: row.get(format!("{}_currency", column).as_str())?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sqlite::{read, write};
+    use crate::{Money, Transaction};
+    use chrono::NaiveDate;
+    use decimal::d128;
+    use futures::StreamExt;
+    use rusqlite::Connection;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let date = |day| NaiveDate::from_ymd_opt(2021, 1, day).unwrap();
+        let fee = Money::new(d128!(-0.5));
+        let transactions = vec![
+            Transaction::new_unchecked(
+                date(20),
+                "isin".to_string(),
+                -1,
+                Money::with_currency(d128!(12.5), "EUR".to_string()),
+                "id".to_string(),
+            )
+            .with_fee(fee.clone()),
+            Transaction::new_unchecked(
+                date(3),
+                "isin".to_string(),
+                2,
+                Money::with_currency(d128!(-20.25), "EUR".to_string()),
+                "id".to_string(),
+            )
+            .with_fee(fee),
+        ];
+
+        let conn = Connection::open_in_memory().unwrap();
+        write(&conn, &transactions).unwrap();
+        let read: Vec<_> = read(&conn).unwrap().map(|tr| tr.unwrap()).collect().await;
+        assert_eq!(read, vec![transactions[1].clone(), transactions[0].clone()]);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::money::Error;
+use crate::portfolio::Report;
+use crate::Money;
+use serde::Serialize;
+use std::convert::TryFrom;
+
+/// The fields of a capital gains tax form for the year of a `Report`,
+/// serializable to JSON or as a CSV row
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TaxForm {
+    pub year: i32,
+    /// the value of the sells
+    pub total_proceeds: Money,
+    /// the cost basis of the sold shares
+    pub total_cost: Money,
+    /// the proceeds minus the cost
+    pub net_gain: Money,
+    /// the losses of earlier years offsetting the gain
+    pub carried_losses_applied: Money,
+    /// the gain minus the carried losses and the annual exemption,
+    /// not below zero
+    pub taxable_gain: Money,
+}
+
+impl TryFrom<&Report> for TaxForm {
+    type Error = Error;
+
+    fn try_from(report: &Report) -> Result<Self, Error> {
+        let year = report.year();
+        let total_cost = report.sold_cost()?;
+        let mut net_gain = report.gains(year);
+        net_gain.add(&report.losses(year))?;
+        let mut total_proceeds = total_cost.clone();
+        total_proceeds.add(&net_gain)?;
+        let carried_losses_applied = report.carried_losses_applied()?;
+
+        let mut taxable_gain = net_gain.clone();
+        taxable_gain.sub(&carried_losses_applied)?;
+        if let Some(exemption) = report.annual_exemption() {
+            taxable_gain.sub(exemption)?;
+        }
+        if taxable_gain.is_negative() {
+            taxable_gain = Money::default();
+        }
+
+        Ok(Self {
+            year,
+            total_proceeds: total_proceeds.truncate_trailing_zeros(),
+            total_cost: total_cost.truncate_trailing_zeros(),
+            net_gain: net_gain.truncate_trailing_zeros(),
+            carried_losses_applied: carried_losses_applied.truncate_trailing_zeros(),
+            taxable_gain: taxable_gain.truncate_trailing_zeros(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::portfolio::Portfolio;
+    use crate::taxform::TaxForm;
+    use crate::test::trade;
+    use crate::Money;
+    use chrono::NaiveDate;
+    use decimal::d128;
+    use futures::{stream, StreamExt};
+    use std::convert::TryFrom;
+
+    #[tokio::test]
+    async fn from_report() {
+        let date = |year| NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+        let transactions = vec![
+            trade(date(2020), "1", 2, -200),
+            trade(date(2020), "1", -1, 70),
+            trade(date(2021), "1", -1, 250),
+        ];
+
+        let report = Portfolio::with_carry_losses(stream::iter(transactions).map(Ok), 1)
+            .annual_exemption(Money::new(d128::from(50)))
+            .report(2021)
+            .await
+            .unwrap();
+        let form = TaxForm::try_from(&report).unwrap();
+
+        assert_eq!(
+            form,
+            TaxForm {
+                year: 2021,
+                total_proceeds: Money::new(d128::from(250)),
+                total_cost: Money::new(d128::from(100)),
+                net_gain: Money::new(d128::from(150)),
+                carried_losses_applied: Money::new(d128::from(30)),
+                taxable_gain: Money::new(d128::from(70)),
+            }
+        );
+
+        let mut csv = csv::Writer::from_writer(vec![]);
+        csv.serialize(&form).unwrap();
+        assert_eq!(
+            String::from_utf8(csv.into_inner().unwrap()).unwrap(),
+            "year,total_proceeds,total_cost,net_gain,carried_losses_applied,taxable_gain\n\
+             2021,250,100,150,30,70\n"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::path::Path;
+use std::process::Command;
+
+fn run(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_degiro-tax-report"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:#?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn rounding_modes() {
+    let args = ["-f", "./testdata/rounding.csv", "-y", "2021"];
+
+    let truncated = run(&[&args[..], &["-r", "truncate", "unadjusted"]].concat());
+    let half_up = run(&[&args[..], &["-r", "half-up", "unadjusted"]].concat());
+
+    assert_eq!(truncated, "report: amount: 0");
+    assert_eq!(half_up, "report: amount: 0.01");
+}
+
+#[test]
+fn config_file() {
+    let args = ["-f", "./testdata/rounding.csv"];
+    let config = ["--config", "./testdata/rounding.toml"];
+
+    let from_config = run(&[&args[..], &config, &["unadjusted"]].concat());
+    let overridden = run(&[&args[..], &config, &["-r", "truncate", "unadjusted"]].concat());
+
+    assert_eq!(from_config, "report: amount: 0.01");
+    assert_eq!(overridden, "report: amount: 0");
+}
+
+#[test]
+fn directory() {
+    let out = run(&["--dir", "./testdata/accounts", "-y", "2021", "unadjusted"]);
+    assert_eq!(
+        out,
+        "./testdata/accounts/a.csv: amount: 0\n./testdata/accounts/b.csv: amount: -20\nreport: amount: -19.99"
+    );
+}
+
+#[test]
+fn non_ascii_delimiter() {
+    let output = Command::new(env!("CARGO_BIN_EXE_degiro-tax-report"))
+        .args(["-f", "./testdata/rounding.csv", "-y", "2021", "-d", "§"])
+        .arg("unadjusted")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("`§` is not an ASCII character"));
+}
+
+#[test]
+fn empty_directory() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("empty");
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_degiro-tax-report"))
+        .args(["--dir", dir.to_str().unwrap(), "-y", "2021"])
+        .arg("unadjusted")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("error: no csv files in"));
+}
+
+#[test]
+fn user_errors() {
+    let error = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_degiro-tax-report"))
+            .args(args)
+            .arg("unadjusted")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    assert_eq!(
+        error(&["-f", "./testdata/missing.csv", "-y", "2021"]),
+        "error: cannot open ./testdata/missing.csv: No such file or directory (os error 2)\n"
+    );
+    assert_eq!(
+        error(&["-f", "./testdata/rounding.csv"]),
+        "error: the year is missing from the flags and the config file\n"
+    );
+    assert!(error(&["-f", "./testdata/rounding.csv", "-y", "2019"])
+        .starts_with("error: ./testdata/rounding.csv: no data for year: 2019"));
+    assert!(error(&[
+        "-f",
+        "./testdata/rounding.csv",
+        "--config",
+        "./testdata/rounding.csv"
+    ])
+    .starts_with("error: invalid config file ./testdata/rounding.csv: "));
+}
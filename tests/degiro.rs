@@ -1,21 +1,472 @@
-use csv::DeserializeRecordsIter;
+use chrono::NaiveDate;
 use decimal::d128;
+use degiro_tax_report::account::{AccountCsvStream, AccountEventKind};
 use degiro_tax_report::money::Money;
-use degiro_tax_report::portfolio::Portfolio;
-use degiro_tax_report::{CsvStream, Transaction};
+use degiro_tax_report::portfolio::{Issue, Portfolio, ReportStyle, Warning};
+use degiro_tax_report::{
+    detect_currency, report_dir, sorted, CsvStream, Transaction, TransactionType,
+};
+use futures::StreamExt;
 use std::fs::File;
 
 #[tokio::test]
 async fn parse_and_calculate() {
     env_logger::init();
     let f = File::open("./testdata/data.csv").unwrap();
-    let mut rdr = csv::Reader::from_reader(f);
-    let iter: DeserializeRecordsIter<File, Transaction> = rdr.deserialize();
-    let tr_stream = CsvStream::new(iter);
+    let tr_stream = CsvStream::new(f).unwrap();
 
     let portfolio = Portfolio::with_carry_losses(tr_stream, 5);
 
     let report = portfolio.report(2021).await.unwrap();
 
-    assert_eq!(report.adjusted_profit().unwrap(), Money::new(d128!(0)))
+    assert_eq!(
+        report.adjusted_profit().unwrap(),
+        Money::new(d128!(-1332.450641738611378080068782156071))
+    )
+}
+
+#[tokio::test]
+async fn parse_semicolon_delimited() {
+    let f = File::open("./testdata/data.csv").unwrap();
+    let comma = Portfolio::with_carry_losses(CsvStream::new(f).unwrap(), 5)
+        .report(2021)
+        .await
+        .unwrap();
+
+    let f = File::open("./testdata/data_semicolon.csv").unwrap();
+    let semicolon = Portfolio::with_carry_losses(CsvStream::new(f).unwrap(), 5)
+        .report(2021)
+        .await
+        .unwrap();
+
+    assert_eq!(semicolon.profit().unwrap(), comma.profit().unwrap());
+    assert_eq!(
+        semicolon.adjusted_profit().unwrap(),
+        comma.adjusted_profit().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn transactions_json_round_trip() {
+    let f = File::open("./testdata/data.csv").unwrap();
+    let mut transactions: Vec<Transaction> = CsvStream::new(f)
+        .unwrap()
+        .map(|tr| tr.unwrap())
+        .collect()
+        .await;
+
+    transactions.push(Transaction::new_unchecked(
+        NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(),
+        "1".to_string(),
+        1,
+        Money::with_currency(d128!(-500.50), "EUR".to_string()),
+        "id".to_string(),
+    ));
+
+    let json = serde_json::to_string(&transactions).unwrap();
+    let parsed: Vec<Transaction> = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(parsed, transactions);
+}
+
+#[tokio::test]
+async fn into_base_currency() {
+    let f = File::open("./testdata/usd.csv").unwrap();
+    let transactions: Vec<Transaction> = CsvStream::new(f)
+        .unwrap()
+        .into_base_currency("EUR")
+        .map(|tr| tr.unwrap())
+        .collect()
+        .await;
+
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(
+        transactions[0].value(),
+        &Money::with_currency(d128!(-100), "EUR".to_string())
+    );
+}
+
+#[test]
+fn transaction_from_csv_line() {
+    let line = "07-07-2021,12:42,ISHARES NASDAQ-100 UCITS ETF (DE),DE000A0F5UF5,XET,XETA,-21,122.2600,EUR,2567.46,EUR,2567.46,EUR,,,,2567.46,EUR,9d6f06cb-6bef-46c4-9b46-159052d052d9";
+    let tr = Transaction::from_csv_line(line).unwrap();
+
+    assert_eq!(*tr.date(), NaiveDate::from_ymd_opt(2021, 7, 7).unwrap());
+    assert_eq!(tr.product(), "ISHARES NASDAQ-100 UCITS ETF (DE)");
+    assert_eq!(tr.isin(), "DE000A0F5UF5");
+    assert_eq!(tr.quantity(), -21);
+    assert_eq!(*tr.value(), Money::new(d128!(2567.46)));
+    assert_eq!(tr.fee(), None);
+
+    assert!(Transaction::from_csv_line("07-07-2021,not a transaction").is_err());
+}
+
+#[tokio::test]
+async fn sorted_shuffled() {
+    let f = File::open("./testdata/data.csv").unwrap();
+    let expected = Portfolio::with_carry_losses(CsvStream::new(f).unwrap(), 5)
+        .report(2021)
+        .await
+        .unwrap();
+
+    let f = File::open("./testdata/data.csv").unwrap();
+    let mut transactions: Vec<_> = CsvStream::new(f).unwrap().collect().await;
+    // a fixed permutation keeps the test deterministic
+    let len = transactions.len();
+    for i in 0..len {
+        transactions.swap(i, (i * 7919 + 13) % len);
+    }
+
+    let report = Portfolio::with_carry_losses(sorted(futures::stream::iter(transactions)), 5)
+        .report(2021)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        report.adjusted_profit().unwrap(),
+        expected.adjusted_profit().unwrap()
+    );
+}
+
+#[cfg(feature = "source-hash")]
+#[tokio::test]
+async fn source_hash_is_stable() {
+    let hash = || {
+        let f = File::open("./testdata/data.csv").unwrap();
+        CsvStream::new(f).unwrap().source_hash().to_string()
+    };
+    let first = hash();
+
+    assert_eq!(first.len(), 64);
+    assert_eq!(first, hash());
+
+    let f = File::open("./testdata/data.csv").unwrap();
+    let tr_stream = CsvStream::new(f).unwrap();
+    let report = Portfolio::new(tr_stream)
+        .source_hash(&first)
+        .report(2021)
+        .await
+        .unwrap();
+    assert_eq!(report.source_hash(), Some(first.as_str()));
+}
+
+#[tokio::test]
+async fn headers_only() {
+    let f = File::open("./testdata/empty.csv").unwrap();
+    let report = Portfolio::new(CsvStream::new(f).unwrap())
+        .report(2021)
+        .await
+        .unwrap();
+
+    assert_eq!(report.profit().unwrap(), Money::default());
+    assert_eq!(report.adjusted_profit().unwrap(), Money::default());
+    assert_eq!(report.warnings(), &[Warning::NoTransactions]);
+}
+
+#[tokio::test]
+async fn order_ids() {
+    let line = "07-07-2021,12:42,ISHARES NASDAQ-100 UCITS ETF (DE),DE000A0F5UF5,XET,XETA,2,122.2600,EUR,-244.52,EUR,-244.52,EUR,,,,-244.52,EUR,";
+    let blank = Transaction::from_csv_line(line).unwrap();
+    assert_eq!(blank.reference(), "XET");
+    assert_eq!(blank.order_id(), "");
+
+    let line = format!("{}9d6f06cb-6bef-46c4-9b46-159052d052d9", line);
+    let tr = Transaction::from_csv_line(&line).unwrap();
+    assert_eq!(tr.order_id(), "9d6f06cb-6bef-46c4-9b46-159052d052d9");
+
+    let issues = Portfolio::new(futures::stream::iter(vec![Ok(tr), Ok(blank)]))
+        .validate()
+        .await
+        .unwrap();
+    assert_eq!(
+        issues,
+        vec![Issue::BlankOrderId {
+            isin: "DE000A0F5UF5".to_string(),
+            date: NaiveDate::from_ymd_opt(2021, 7, 7).unwrap(),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn from_path() {
+    let report =
+        Portfolio::with_carry_losses(CsvStream::from_path("./testdata/data.csv").unwrap(), 5)
+            .report(2021)
+            .await
+            .unwrap();
+    assert_eq!(
+        report.adjusted_profit().unwrap(),
+        Money::new(d128!(-1332.450641738611378080068782156071))
+    );
+
+    let err = CsvStream::from_path("./testdata/missing.csv")
+        .err()
+        .unwrap();
+    assert!(err
+        .to_string()
+        .starts_with("cannot open ./testdata/missing.csv"));
+}
+
+#[tokio::test]
+async fn custom_headers() {
+    let headers = "Order ID,Date,Time,Product,ISIN,Reference,Venue,Quantity,Price,,Local value,,Value,,Exchange rate,Transaction and/or third,,Total,";
+    let f = File::open("./testdata/data_reordered.csv").unwrap();
+    let tr_stream = CsvStream::with_headers(f, headers).unwrap();
+
+    let report = Portfolio::with_carry_losses(tr_stream, 5)
+        .report(2021)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        report.adjusted_profit().unwrap(),
+        Money::new(d128!(-1332.450641738611378080068782156071))
+    )
+}
+
+#[test]
+fn blank_time() {
+    let line = "07-07-2015,,ISHARES NASDAQ-100 UCITS ETF (DE),DE000A0F5UF5,XET,XETA,-21,122.2600,EUR,2567.46,EUR,2567.46,EUR,,,,2567.46,EUR,9d6f06cb-6bef-46c4-9b46-159052d052d9";
+    let tr = Transaction::from_csv_line(line).unwrap();
+
+    assert_eq!(*tr.date(), NaiveDate::from_ymd_opt(2015, 7, 7).unwrap());
+    assert_eq!(tr.time(), None);
+}
+
+#[tokio::test]
+async fn degiro_pnl_style() {
+    // per product totals in the conventions of the DeGiro overview
+    let expected: Vec<(String, Money)> = std::fs::read_to_string("./testdata/pnl_expected.csv")
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let (isin, profit) = line.split_once(',').unwrap();
+            (isin.to_string(), profit.parse().unwrap())
+        })
+        .collect();
+
+    let report = Portfolio::new(CsvStream::from_path("./testdata/pnl.csv").unwrap())
+        .style(ReportStyle::DegiroPnl)
+        .report(2021)
+        .await
+        .unwrap();
+    let products: Vec<(String, Money)> = report
+        .profit_by_product()
+        .into_iter()
+        .map(|(isin, profit)| (isin, profit.truncate_trailing_zeros()))
+        .collect();
+
+    assert_eq!(products, expected);
+}
+
+#[tokio::test]
+async fn detect_currencies() {
+    let tr_stream = CsvStream::from_path("./testdata/eur.csv").unwrap();
+    let currency = detect_currency(tr_stream, 10).await.unwrap();
+    assert_eq!(currency.as_deref(), Some("eur"));
+
+    let tr_stream = CsvStream::from_path("./testdata/mixed.csv").unwrap();
+    let err = detect_currency(tr_stream, 10).await.err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        r#"transactions in multiple currencies: {"eur", "usd"}"#
+    );
+}
+
+#[tokio::test]
+async fn unsigned_quantities() {
+    let transactions: Vec<_> = CsvStream::from_path("./testdata/unsigned.csv")
+        .unwrap()
+        .unsigned_quantities(true)
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    let quantities: Vec<_> = transactions.iter().map(|tr| tr.quantity()).collect();
+    assert_eq!(quantities, vec![9, -9]);
+}
+
+#[tokio::test]
+async fn crlf_line_endings() {
+    let parse = |path| async move {
+        CsvStream::from_path(path)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await
+    };
+    let lf = parse("./testdata/data.csv").await;
+    let crlf = parse("./testdata/data_crlf.csv").await;
+
+    assert_eq!(crlf, lf);
+    assert_eq!(crlf[0].order_id(), "139a0ebb-da47-4509-b89c-5bad83b59915");
+}
+
+#[tokio::test]
+async fn directory_of_files() {
+    let (combined, files) = report_dir("./testdata/accounts", 2021, |path| {
+        Ok(Portfolio::new(CsvStream::from_path(path)?))
+    })
+    .await
+    .unwrap();
+
+    let names: Vec<_> = files
+        .iter()
+        .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["a.csv", "b.csv"]);
+    assert_eq!(files[0].1.profit().unwrap(), Money::new(d128!(0.005)));
+    assert_eq!(files[1].1.profit().unwrap(), Money::new(d128!(-20)));
+
+    assert_eq!(combined.profit().unwrap(), Money::new(d128!(-19.995)));
+    assert_eq!(combined.gains(2021), Money::new(d128!(0.005)));
+    assert_eq!(combined.losses(2021), Money::new(d128!(-20)));
+    assert_eq!(combined.processed().sells, 2);
+}
+
+#[tokio::test]
+async fn strict_dates() {
+    let transactions: Vec<_> = CsvStream::from_path("./testdata/slash_date.csv")
+        .unwrap()
+        .collect()
+        .await;
+    let buy = transactions[0].as_ref().unwrap();
+    assert_eq!(*buy.date(), NaiveDate::from_ymd_opt(2021, 7, 7).unwrap());
+
+    let transactions: Vec<_> = CsvStream::from_path("./testdata/slash_date.csv")
+        .unwrap()
+        .strict_dates(true)
+        .collect()
+        .await;
+    assert!(transactions[1].is_ok());
+    let err = transactions[0].as_ref().err().unwrap();
+    assert!(err
+        .to_string()
+        .starts_with("date: 07/07/2021 is not DD-MM-YYYY"));
+}
+
+#[tokio::test]
+async fn date_range() {
+    let range = CsvStream::from_path("./testdata/data.csv")
+        .unwrap()
+        .date_range()
+        .await
+        .unwrap();
+    assert_eq!(
+        range,
+        Some((
+            NaiveDate::from_ymd_opt(2019, 7, 16).unwrap(),
+            NaiveDate::from_ymd_opt(2021, 7, 7).unwrap()
+        ))
+    );
+
+    let range = CsvStream::from_path("./testdata/empty.csv")
+        .unwrap()
+        .date_range()
+        .await
+        .unwrap();
+    assert_eq!(range, None);
+}
+
+#[tokio::test]
+async fn filter_type() {
+    let all: Vec<_> = CsvStream::from_path("./testdata/data.csv")
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    let sells: Vec<_> = CsvStream::from_path("./testdata/data.csv")
+        .unwrap()
+        .filter_type(TransactionType::Sell)
+        .map(Result::unwrap)
+        .collect()
+        .await;
+
+    assert!(!sells.is_empty());
+    assert!(sells.len() < all.len());
+    assert!(sells.iter().all(|tr| tr.r#type() == TransactionType::Sell));
+    assert_eq!(
+        sells.len(),
+        all.iter()
+            .filter(|tr| tr.r#type() == TransactionType::Sell)
+            .count()
+    );
+}
+
+#[tokio::test]
+async fn decimal_comma() {
+    let dots: Vec<_> = CsvStream::from_path("./testdata/data_semicolon.csv")
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    let commas: Vec<_> = CsvStream::from_path("./testdata/data_decimal_comma.csv")
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+
+    // `-2.767,50` is read as -2767.50
+    assert_eq!(commas, dots);
+    assert_eq!(*commas.last().unwrap().value(), Money::new(d128!(-2767.50)));
+}
+
+#[tokio::test]
+async fn file_order() {
+    let newest_first = Portfolio::new(CsvStream::from_path("./testdata/data.csv").unwrap())
+        .report(2021)
+        .await
+        .unwrap();
+    let oldest_first =
+        Portfolio::new(CsvStream::from_path("./testdata/data_ascending.csv").unwrap())
+            .report(2021)
+            .await
+            .unwrap();
+    assert_eq!(
+        oldest_first.profit().unwrap(),
+        newest_first.profit().unwrap()
+    );
+    assert_eq!(oldest_first.processed(), newest_first.processed());
+
+    let err = Portfolio::new(CsvStream::from_path("./testdata/unordered.csv").unwrap())
+        .report(2021)
+        .await
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("transactions are not in order, sort them first"));
+}
+
+#[tokio::test]
+async fn account_statement() {
+    let events: Vec<_> = AccountCsvStream::from_path("./testdata/account.csv")
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    let eur = |amount: d128| Money::with_currency(amount, "EUR".to_string());
+
+    // oldest first and without the cash sweep
+    assert_eq!(
+        events
+            .iter()
+            .map(|event| (event.kind, event.change.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (AccountEventKind::Deposit, eur(d128!(3000.00))),
+            (AccountEventKind::Interest, eur(d128!(-0.12))),
+            (AccountEventKind::Trade, eur(d128!(-2767.50))),
+            (AccountEventKind::Fee, eur(d128!(-2.83))),
+            (AccountEventKind::Withdrawal, eur(d128!(-500.00))),
+            (AccountEventKind::Dividend, eur(d128!(4.04))),
+            (AccountEventKind::DividendTax, eur(d128!(-0.61))),
+        ]
+    );
+    let dividend = &events[5];
+    assert_eq!(dividend.isin, "IE00B3RBWM25");
+    assert_eq!(
+        dividend.date,
+        NaiveDate::from_ymd_opt(2021, 12, 28).unwrap()
+    );
+    assert_eq!(dividend.balance, Some(eur(d128!(104.04))));
 }